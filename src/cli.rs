@@ -1,18 +1,52 @@
+use crate::date::DateFormat;
+use crate::output::{Checksum, Compression, Format, OutputTemplate};
+use crate::partition::PartitionFormat;
+use crate::progress::BarStyle;
+use crate::transform::Transform;
+use estunnel::scroll::Ttl;
+use reqwest::header::{HeaderName, HeaderValue};
+use std::fmt;
 use std::path::PathBuf;
+use std::str::FromStr;
 use structopt::clap;
 
-pub use structopt::clap::{AppSettings, Shell};
+pub use structopt::clap::AppSettings;
 pub use structopt::StructOpt;
 
 #[derive(StructOpt, Debug)]
 #[structopt(global_settings(&[AppSettings::ColoredHelp]))]
+#[allow(clippy::large_enum_variant)]
 pub enum Opt {
     /// Pull data from ElasticSearch
+    ///
+    /// Send SIGUSR1 to pause a running pull after the current pages and SIGUSR2 to resume it. The scrolls of paused
+    /// slices are kept alive by fetching a page ahead every half of --ttl.
     #[structopt(name = "pull")]
     Pull(PullOpt),
+    /// Run a pull defined in a TOML job file
+    #[structopt(name = "run")]
+    Run(RunOpt),
+    /// List indices with their doc counts, sizes and shard counts
+    #[structopt(name = "indices")]
+    Indices(IndicesOpt),
+    /// Export the buckets of a composite aggregation, paging through them with `after` keys
+    #[structopt(name = "agg")]
+    Agg(AggOpt),
+    /// Report the version of the cluster and the settings of an index which matter for pulling it
+    #[structopt(name = "probe")]
+    Probe(ProbeOpt),
+    /// Check a query with `_validate/query` and print how the cluster rewrites it, without running it
+    #[structopt(name = "validate")]
+    Validate(ValidateOpt),
+    /// Copy docs of an index into another cluster through `_bulk`, without writing them to disk
+    #[structopt(name = "reindex")]
+    Reindex(ReindexOpt),
     /// Generate shell completion file
     #[structopt(name = "completion")]
     Completion(CompletionOpt),
+    /// Print the names of the indices starting with a prefix one per line, for completion scripts to call
+    #[structopt(name = "complete-indices")]
+    CompleteIndices(CompleteIndicesOpt),
     /// Check for updates
     #[structopt(name = "update")]
     Update,
@@ -25,20 +59,216 @@ pub struct CompletionOpt {
 }
 
 #[derive(StructOpt, Debug)]
-pub struct PullOpt {
-    /// ElasticSearch host url
+pub struct CompleteIndicesOpt {
+    #[structopt(flatten)]
+    pub conn: ConnOpt,
+
+    /// Word being completed, indices starting with `.` are only listed if it does
+    ///
+    /// Pass the password with `--user <user>:<password>`, as a prompt would block the completion.
+    #[structopt(default_value = "")]
+    pub prefix: String,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct RunOpt {
+    /// Job file, whose keys are long options of `pull` like `index = "logs"` or `slice = 4`
+    ///
+    /// Flags are set with `true`, repeatable options take arrays and `query` may be an inline table. The password is
+    /// read from the environment variable named by `password_env` rather than written in the file. Relative paths of
+    /// `query`, `coerce`, `header_file` and `watermark_file` are resolved against the directory of the job file.
+    pub job: PathBuf,
+}
+
+/// Options of the connection to the cluster, shared by the subcommands talking to it.
+#[derive(StructOpt, Debug, Clone)]
+pub struct ConnOpt {
+    /// ElasticSearch host url, use `unix:///path/to/socket` to connect through a unix domain socket
+    ///
+    /// It may have a base path like `https://gw/es` for a cluster behind a path-based reverse proxy. A unix socket is
+    /// reached through a port on 127.0.0.1, which other local users can connect to but which drops connections
+    /// without a random token of the process.
     #[structopt(short = "h", long = "host", default_value = "http://localhost:9200")]
     pub host: String,
 
-    /// Username for http basic authorization
+    /// Connect directly, ignoring proxies set by environment variables like HTTPS_PROXY
+    #[structopt(long = "no-proxy")]
+    pub no_proxy: bool,
+
+    /// Extra http header in `Key: Value` form sent with every request, can be repeated
+    #[structopt(short = "H", long = "header", number_of_values = 1, parse(try_from_str = parse_header))]
+    pub headers: Vec<(String, String)>,
+
+    /// File of extra http headers, one `Key: Value` per line, merged with --header
+    ///
+    /// Blank lines and lines starting with `#` are skipped. Keeping headers which carry tokens in a file keeps them out
+    /// of the shell history.
+    #[structopt(long = "header-file")]
+    pub header_file: Option<PathBuf>,
+
+    /// Server name sent in the TLS handshake and checked against the certificate, for an https --host by ip address
+    ///
+    /// The connections still go to the address of --host, through a local tunnel which replaces the proxies set by
+    /// environment variables. It is also sent as the Host header unless --host-header is given.
+    #[structopt(long = "tls-sni")]
+    pub tls_sni: Option<String>,
+
+    /// Host header sent with every request instead of the host of --host, like the name a load balancer routes by
+    #[structopt(long = "host-header")]
+    pub host_header: Option<String>,
+
+    /// Max idle connections kept open to the host by the http client shared by all slices
+    ///
+    /// Sharing one client saves a TLS handshake and a file descriptor per slice for each new connection, at the cost
+    /// of keeping up to this many sockets and their buffers alive between requests. Set it to the slice count to let
+    /// every slice reuse a connection, or lower it to save descriptors. Connections through a unix socket are never
+    /// reused.
+    #[structopt(long = "pool-max-idle-per-host")]
+    pub pool_max_idle_per_host: Option<usize>,
+
+    /// How long an idle connection is kept open for reuse like `90s`, with the units of --ttl
+    #[structopt(long = "pool-idle-timeout")]
+    pub pool_idle_timeout: Option<Ttl>,
+
+    /// Username for http basic authorization, requests are anonymous without it
     #[structopt(short = "u", long = "user")]
     pub user: Option<String>,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct IndicesOpt {
+    #[structopt(flatten)]
+    pub conn: ConnOpt,
+
+    /// Only list indices matching this pattern, like `logs-*`
+    #[structopt(default_value = "*")]
+    pub pattern: String,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct ProbeOpt {
+    #[structopt(flatten)]
+    pub conn: ConnOpt,
 
     /// Target index name(or alias)
     #[structopt(short = "i", long = "index")]
     pub index: String,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct ValidateOpt {
+    #[structopt(flatten)]
+    pub conn: ConnOpt,
+
+    /// Target index name(or alias)
+    #[structopt(short = "i", long = "index")]
+    pub index: String,
+
+    /// File path for query body, or the body itself if it starts with `{`
+    ///
+    /// Only its `query` is validated, other parts of a search body like `sort` or `size` are left out.
+    #[structopt(short = "q", long = "query")]
+    pub query: Option<PathBuf>,
+
+    /// Explain the query on every shard, as a rewrite may differ between shards
+    #[structopt(long = "all-shards")]
+    pub all_shards: bool,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct AggOpt {
+    #[structopt(flatten)]
+    pub conn: ConnOpt,
+
+    /// Target index name(or alias)
+    #[structopt(short = "i", long = "index")]
+    pub index: String,
+
+    /// File path for query body with a composite aggregation, or the body itself if it starts with `{`
+    ///
+    /// e.g. `{"aggs": {"users": {"composite": {"sources": [{"user": {"terms": {"field": "user"}}}]}}}}`. Each bucket
+    /// is written as a json line.
+    #[structopt(short = "q", long = "query")]
+    pub query: Option<PathBuf>,
+
+    /// Buckets per page [default: size of the composite aggregation]
+    #[structopt(short = "b", long = "batch")]
+    pub size: Option<u64>,
+
+    /// File path for output
+    #[structopt(short = "o", long = "output", default_value = "/dev/stdout")]
+    pub output: PathBuf,
+
+    /// Compress the output with none, gzip or zstd, the matching extension is appended to the output path
+    #[structopt(short = "z", long = "compress", default_value = "none")]
+    pub compress: Compression,
+
+    /// Compression level, 0-9 for gzip and 1-22 for zstd, whose defaults are 6 and 3
+    ///
+    /// Low levels keep the compression from slowing down the pull on fast disks, high levels save space and
+    /// bandwidth. The level applies to every output file.
+    #[structopt(long = "compress-level")]
+    pub compress_level: Option<u32>,
+
+    /// Show error responses of the cluster as their type, reason and root causes on lines of their own
+    #[structopt(long = "pretty-error")]
+    pub pretty_error: bool,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct ReindexOpt {
+    /// Source ElasticSearch host url, `unix:///path/to/socket` connects through a unix domain socket
+    #[structopt(long = "from-host", default_value = "http://localhost:9200")]
+    pub from_host: String,
+
+    /// Username of the source for http basic authorization
+    #[structopt(long = "from-user")]
+    pub from_user: Option<String>,
+
+    /// Destination ElasticSearch host url, `unix:///path/to/socket` connects through a unix domain socket
+    #[structopt(long = "to-host")]
+    pub to_host: String,
+
+    /// Username of the destination for http basic authorization
+    #[structopt(long = "to-user")]
+    pub to_user: Option<String>,
+
+    /// Connect directly, ignoring proxies set by environment variables like HTTPS_PROXY
+    #[structopt(long = "no-proxy")]
+    pub no_proxy: bool,
+
+    /// Source index name(or alias)
+    #[structopt(short = "i", long = "index")]
+    pub index: String,
+
+    /// Destination index name [default: the index each doc comes from]
+    #[structopt(long = "dest-index")]
+    pub dest_index: Option<String>,
+
+    /// Destination index of each doc by its date, like `logs-{date}` for daily indices out of one big index
+    ///
+    /// `{date}` is the date of --dest-date-field formatted by --dest-date-format. Dates are epoch milliseconds or
+    /// ISO 8601 strings and are taken in UTC, docs without a date go to --dest-fallback-index.
+    #[structopt(
+        long = "dest-index-template",
+        conflicts_with = "dest-index",
+        requires = "dest-fallback-index"
+    )]
+    pub dest_index_template: Option<String>,
+
+    /// Date field in the source of the docs for --dest-index-template, a dotted name for nested fields
+    #[structopt(long = "dest-date-field", default_value = "@timestamp")]
+    pub dest_date_field: String,
+
+    /// Format of `{date}` in --dest-index-template, of %Y, %m, %d and %H
+    #[structopt(long = "dest-date-format", default_value = "%Y.%m.%d")]
+    pub dest_date_format: DateFormat,
 
-    /// File path for query body
+    /// Destination index of the docs of --dest-index-template with a missing or unparseable date
+    #[structopt(long = "dest-fallback-index", requires = "dest-index-template")]
+    pub dest_fallback_index: Option<String>,
+
+    /// File path for query body, or the body itself if it starts with `{`
     #[structopt(short = "q", long = "query")]
     pub query: Option<PathBuf>,
 
@@ -46,7 +276,147 @@ pub struct PullOpt {
     #[structopt(short = "s", long = "slice", default_value = "1")]
     pub slice: u64,
 
+    /// Scroll batch size
+    #[structopt(short = "b", long = "batch", default_value = "1000")]
+    pub batch: u64,
+
+    /// Docs per bulk request to the destination
+    #[structopt(long = "bulk-size", default_value = "1000")]
+    pub bulk_size: usize,
+
+    /// Refresh of the destination after each bulk request, `wait_for` makes the docs searchable before the next one
+    #[structopt(long = "refresh", possible_values = &["true", "false", "wait_for"])]
+    pub refresh: Option<String>,
+
+    /// Ingest pipeline the destination runs the docs through
+    #[structopt(long = "pipeline")]
+    pub pipeline: Option<String>,
+
+    /// Action of each doc, `create` keeps docs which exist in the destination instead of overwriting them
+    #[structopt(long = "op-type", default_value = "index", possible_values = &["index", "create"])]
+    pub op_type: String,
+
+    /// Max requests in flight at the same time, counting both the scroll requests to the source and the bulk
+    /// requests to the destination
+    #[structopt(long = "max-concurrent-requests")]
+    pub max_concurrent_requests: Option<usize>,
+
+    /// Scroll session ttl, a number with one of the units d, h, m, s, ms, micros and nanos
+    #[structopt(long = "ttl", default_value = "1m")]
+    pub ttl: Ttl,
+
+    /// Progress display: bar, spinner, aggregate for a single bar of all slices, or plain for a periodic status line
+    #[structopt(long = "progress-style", alias = "progress", default_value = "bar")]
+    pub progress: BarStyle,
+
+    /// Write to the destination without asking for confirmation, as needed without a terminal
+    #[structopt(short = "y", long = "assume-yes")]
+    pub assume_yes: bool,
+}
+
+#[derive(StructOpt, Debug, Clone)]
+pub struct PullOpt {
+    #[structopt(flatten)]
+    pub conn: ConnOpt,
+
+    /// Target index name(or alias)
+    #[structopt(
+        short = "i",
+        long = "index",
+        required_unless = "index-file",
+        default_value_if("index-file", None, "")
+    )]
+    pub index: String,
+
+    /// File of the indices to pull instead of --index, one name per line
+    ///
+    /// Blank lines and lines starting with `#` are ignored. The indices are pulled like with --per-index, so
+    /// --output-template with an {index} placeholder writes each of them to files of its own. Indices that do not
+    /// exist are reported and skipped.
+    #[structopt(long = "index-file")]
+    pub index_file: Option<PathBuf>,
+
+    /// File path for query body, or the body itself if it starts with `{`
+    ///
+    /// It can also be a directory of queries named like `<index>.json`, then each index matching --index is pulled
+    /// with its own query to the files of --output-template, and indices without a query are skipped.
+    #[structopt(short = "q", long = "query")]
+    pub query: Option<PathBuf>,
+
+    /// Date field used by --since and --until
+    #[structopt(long = "time-field", default_value = "@timestamp")]
+    pub time_field: String,
+
+    /// Only pull docs whose time field is at or after this time, date math like `now-1d` is supported
+    #[structopt(long = "since")]
+    pub since: Option<String>,
+
+    /// Only pull docs whose time field is before this time, date math like `now-1d` is supported
+    #[structopt(long = "until")]
+    pub until: Option<String>,
+
+    /// Scroll slice count, or `auto` for one slice per shard of the index
+    #[structopt(short = "s", long = "slice", default_value = "1")]
+    pub slice: SliceCount,
+
+    /// Pull each index matching --index with a scroll of its own instead of slicing, --slice of them at a time
+    ///
+    /// Suits patterns matching many small indices, where slicing each of them would open a scroll context per
+    /// slice and index. The indices are the slices in the progress and in the {slice} of --output-template, and
+    /// {index} is the index of each of them.
+    #[structopt(long = "per-index", conflicts_with_all = &["only-slice", "shards", "routing"])]
+    pub per_index: bool,
+
+    /// Only pull the slices with these comma separated ids out of --slice, counting from 0 like the `slice.id` of ES
+    ///
+    /// Lets processes on several hosts share an export, e.g. `--slice 16 --only-slice 2` on the third one. Each
+    /// process writes the slices it pulls to its own output, with --output-template their files are named by the
+    /// numbers of the whole scroll.
+    #[structopt(long = "only-slice", use_delimiter = true)]
+    pub only_slice: Vec<u64>,
+
+    /// Only search these shards of the index, like `0-2` or `0,3,5`, which allows splitting an export across processes
+    ///
+    /// The shards are selected with the `preference=_shards:...` search parameter. With `--slice auto` there is one
+    /// slice per selected shard.
+    #[structopt(long = "shards")]
+    pub shards: Option<ShardSet>,
+
+    /// Routing value of the docs, or comma separated values, which limits the search to the shards they are routed to
+    ///
+    /// Much faster for indices whose docs are routed by a key. With `--slice auto` there is one slice per routed shard.
+    #[structopt(long = "routing")]
+    pub routing: Option<String>,
+
+    /// Max slices scrolling at the same time, each of them keeps a scroll context open [default: slice count]
+    ///
+    /// Use it to stay below the `search.max_open_scroll_context` limit of the cluster.
+    #[structopt(long = "max-scroll-contexts", parse(try_from_str = parse_positive))]
+    pub max_scroll_contexts: Option<u64>,
+
+    /// Max requests to the cluster in flight at the same time, however many slices are running
+    ///
+    /// Slices wait for their turn before each request, which keeps a small cluster from being flooded by many slices
+    /// or by pages fetched ahead for a slow output.
+    #[structopt(long = "max-concurrent-requests")]
+    pub max_concurrent_requests: Option<usize>,
+
+    /// Give up a slice after this many retries in total, whether caused by timeouts or by too many scroll contexts
+    #[structopt(long = "max-retries-per-slice")]
+    pub max_retries: Option<u32>,
+
+    /// Retries allowed to all slices together, once they are used up the pull stops and fails
+    ///
+    /// Keeps a degrading cluster from slowing the pull down without end, as each slice retrying a few times adds up.
+    #[structopt(long = "retry-budget")]
+    pub retry_budget: Option<u64>,
+
     /// Scroll batch size (if null size in query body will be used)
+    ///
+    /// Only the opening request of a slice is retried with half the batch size (down to 10) when it times out. Later
+    /// pages of a scroll keep the size of the first one, so a page which times out is retried with the same size,
+    /// counting against --max-retries-per-slice. Elasticsearch rejects scroll batches larger than the
+    /// `index.max_result_window` of the index, the probe subcommand shows it.
     #[structopt(short = "b", long = "batch")]
     pub batch: Option<u64>,
 
@@ -58,7 +428,457 @@ pub struct PullOpt {
     #[structopt(short = "o", long = "output", default_value = "/dev/stdout")]
     pub output: PathBuf,
 
-    /// Scroll session ttl
+    /// Write each slice to a file of its own named like `export-{index}-{slice}.jsonl`, instead of to --output
+    ///
+    /// The placeholders are `{index}`, `{slice}` (required) and `{date}`, the UTC date of the start of the pull.
+    #[structopt(long = "output-template")]
+    pub output_template: Option<OutputTemplate>,
+
+    /// Create the missing parent directories of the output
+    #[structopt(long = "mkdir")]
+    pub mkdir: bool,
+
+    /// Write each doc under --output to the directory of the date of this field, like `out/dt=2023-01-31/part.jsonl`
+    ///
+    /// Makes partitioned tables for a data lake. Dates are epoch milliseconds or ISO 8601 strings and are partitioned
+    /// in UTC, docs without a date go to a `__null__` partition like `dt=__null__`. The directories are created as
+    /// needed.
+    #[structopt(
+        long = "partition-by",
+        conflicts_with_all = &["output-template", "rotate-bytes", "rotate-docs", "benchmark"]
+    )]
+    pub partition_by: Option<String>,
+
+    /// Directory names of --partition-by, of %Y, %m, %d and %H, with `/` between nested directories
+    #[structopt(long = "partition-format", default_value = "dt=%Y-%m-%d")]
+    pub partition_format: PartitionFormat,
+
+    /// Most partitions of --partition-by open at a time, the least recently written one is closed to open another
+    #[structopt(long = "partition-max-open", default_value = "64")]
+    pub partition_max_open: usize,
+
+    /// Extra query string parameter of the search request in `key=value` form, can be repeated
+    ///
+    /// e.g. `--param routing=user1 --param request_cache=false`
+    #[structopt(long = "param", number_of_values = 1, parse(try_from_str = parse_param))]
+    pub params: Vec<(String, String)>,
+
+    /// Only pull docs with at least this score, sets `min_score` of the query
+    ///
+    /// Like the other options setting fields of the query, it takes precedence over the same field of the query file.
+    #[structopt(long = "min-score")]
+    pub min_score: Option<f64>,
+
+    /// Time limit of the search on each shard like `30s`, sets `timeout` of the query
+    ///
+    /// Shards which run out of time return the docs found so far, so the result may be incomplete.
+    #[structopt(long = "search-timeout")]
+    pub search_timeout: Option<Ttl>,
+
+    /// Search type of the search request, `dfs_query_then_fetch` gives more accurate scores for score-ordered exports
+    #[structopt(long = "search-type", possible_values = &["query_then_fetch", "dfs_query_then_fetch"])]
+    pub search_type: Option<String>,
+
+    /// Let the cluster fail the request instead of returning partial results when some shards fail
+    #[structopt(long = "no-partial-results")]
+    pub no_partial_results: bool,
+
+    /// Abort the pull if any shard fails to return its docs, instead of warning about it
+    #[structopt(long = "fail-on-shard-failure")]
+    pub fail_on_shard_failure: bool,
+
+    /// Stop all slices as soon as one of them fails, closing their scrolls
+    #[structopt(long = "fail-fast", conflicts_with = "keep-going")]
+    pub fail_fast: bool,
+
+    /// Let the other slices finish when one of them fails, which is the default
+    ///
+    /// Either way the failed slices are reported at the end and the exit status is non-zero.
+    #[structopt(long = "keep-going")]
+    pub keep_going: bool,
+
+    /// Compress the output with none, gzip or zstd, the matching extension is appended to the output path
+    ///
+    /// Every file is a complete gzip member or zstd frame, so the files of --output-template or of rotation can be
+    /// concatenated into one stream, e.g. `cat export-*.jsonl.gz | gunzip`.
+    #[structopt(short = "z", long = "compress", default_value = "none")]
+    pub compress: Compression,
+
+    /// Compression level, 0-9 for gzip and 1-22 for zstd, whose defaults are 6 and 3
+    ///
+    /// Low levels keep the compression from slowing down the pull on fast disks, high levels save space and
+    /// bandwidth. The level applies to every output file.
+    #[structopt(long = "compress-level")]
+    pub compress_level: Option<u32>,
+
+    /// Write a checksum file of sha256, sha512 or md5 next to each output file, like `out.jsonl.gz.sha256`
+    ///
+    /// The hash is of the bytes in the file, after compression, and is in the format of `sha256sum` so the recipient
+    /// can check it with `sha256sum -c out.jsonl.gz.sha256`.
+    #[structopt(long = "checksum", conflicts_with = "benchmark")]
+    pub checksum: Option<Checksum>,
+
+    /// Roll the output over to numbered files like `out.00001.jsonl` of about this size before compression, e.g.
+    /// `500MB`
+    #[structopt(long = "rotate-bytes", parse(try_from_str = parse_size))]
+    pub rotate_bytes: Option<u64>,
+
+    /// Roll the output over to numbered files like `out.00001.jsonl` of this many docs
+    #[structopt(long = "rotate-docs")]
+    pub rotate_docs: Option<u64>,
+
+    /// Read the output back after the pull and check it is well-formed and complete
+    ///
+    /// Every line must be a json document and the line count must match the written docs. A mismatch with the total
+    /// hits reported by the cluster is only a warning, as the total may be a lower bound (see `track_total_hits`).
+    #[structopt(long = "verify")]
+    pub verify: bool,
+
+    /// Write the doc count expected in the output to this file once all slices have reported their total hits
+    ///
+    /// The count is updated if later pages revise the total, and is capped by --limit. Scroll searches always
+    /// track the total hits accurately, so a consumer may check the line count of the output against it.
+    #[structopt(long = "count-file")]
+    pub count_file: Option<PathBuf>,
+
+    /// Skip documents whose json is larger than this many bytes, so a single huge document cannot exhaust memory
+    #[structopt(long = "max-doc-bytes")]
+    pub max_doc_bytes: Option<usize>,
+
+    /// File to record skipped documents, one json line per document with the reason it was skipped
+    #[structopt(long = "error-file")]
+    pub error_file: Option<PathBuf>,
+
+    /// Convert fields to the types declared in this json file before writing, like `{"user.age": "int"}`
+    ///
+    /// Types are string, int, float and bool. Docs with a value which cannot be converted are skipped and recorded
+    /// in the error file. With `--with-seq-no` the fields are looked up in `_source`.
+    #[structopt(long = "coerce")]
+    pub coerce: Option<PathBuf>,
+
+    /// Transform named fields of the docs before writing, like `base64-decode:user.ssn,card`, can be repeated
+    ///
+    /// The transforms are base64-decode and hex-decode, the decoded bytes have to be UTF-8 text. They run before
+    /// --coerce, in the order given. Docs with a value which cannot be transformed are skipped and recorded in the
+    /// error file. With `--with-seq-no` the fields are looked up in `_source`.
+    #[structopt(long = "transform", number_of_values = 1)]
+    pub transforms: Vec<Transform>,
+
+    /// Transform each doc with a jq filter like `{id: .user_id, name: .user.name}` before writing
+    ///
+    /// Each output of the filter is written as a line, so `select(...)` drops docs and `.items[]` splits them. It runs
+    /// after --coerce. Docs failing the filter are skipped and recorded in the error file.
+    #[structopt(long = "select")]
+    pub select: Option<String>,
+
+    /// Scroll session ttl, a number with one of the units d, h, m, s, ms, micros and nanos
+    ///
+    /// If the output takes a page later than half the ttl, the next page is fetched ahead to keep the scroll alive,
+    /// which holds the pages fetched ahead in memory.
     #[structopt(long = "ttl", default_value = "1m")]
-    pub ttl: String,
+    pub ttl: Ttl,
+
+    /// Leave the scroll of each slice open until its ttl expires instead of clearing it once the slice is done
+    #[structopt(long = "no-scroll-clear")]
+    pub no_scroll_clear: bool,
+
+    /// Only clear all open scroll contexts of the cluster, like those left by an aborted run, and exit
+    ///
+    /// It sends `DELETE /_search/scroll/_all`, which clears the scrolls of all clients of the cluster, not only those
+    /// of estunnel or of --index.
+    #[structopt(long = "clear-only")]
+    pub clear_only: bool,
+
+    /// Clear the scrolls of --clear-only without asking for confirmation, as needed without a terminal
+    #[structopt(short = "y", long = "assume-yes")]
+    pub assume_yes: bool,
+
+    /// Retrieve fields matching the pattern with the fields API (ES 7.11+) and output them instead of `_source`, can
+    /// be repeated
+    ///
+    /// Values are formatted according to the mapping, e.g. `--use-fields-api '*'` for all fields.
+    #[structopt(long = "use-fields-api", number_of_values = 1)]
+    pub fields: Vec<String>,
+
+    /// Retrieve these comma separated fields from doc values and output them instead of `_source`
+    ///
+    /// Cheaper than `_source` for a few keyword or numeric columns, as the cluster does not load the source at all.
+    #[structopt(long = "docvalue-fields", use_delimiter = true)]
+    pub docvalue_fields: Vec<String>,
+
+    /// Output docs with their `_index`, `_id`, `_seq_no` and `_primary_term`, the source goes to `_source`
+    #[structopt(long = "with-seq-no")]
+    pub with_seq_no: bool,
+
+    /// Output the snippets of the `highlight` block of the query along with each doc as `_highlight`
+    ///
+    /// Docs without any highlighted field have none. With `--with-seq-no` the whole hit has them as `highlight`.
+    #[structopt(long = "with-highlight")]
+    pub with_highlight: bool,
+
+    /// Output the nested or child docs matched by the `inner_hits` of the query along with each doc as `_inner_hits`
+    ///
+    /// They keep their structure, keyed by the name of each inner hits like the `inner_hits` of the response. With
+    /// `--with-seq-no` the whole hit has them as `inner_hits`.
+    #[structopt(long = "with-inner-hits")]
+    pub with_inner_hits: bool,
+
+    /// Output format: json for a line per doc, or bulk for the action line and the source line of the `_bulk` API
+    ///
+    /// The action is `{"index":{"_index":...,"_id":...}}` with the routing of the doc if it has one, so the output can
+    /// be sent to `_bulk` as it is. For clusters before 7 it also has the `_type` of the doc. It cannot be used with
+    /// the options replacing `_source`, --select, --with-highlight and --with-inner-hits.
+    #[structopt(long = "format", default_value = "json")]
+    pub format: Format,
+
+    /// Index of the bulk actions of `--format bulk` [default: the index of each doc]
+    #[structopt(long = "bulk-index")]
+    pub bulk_index: Option<String>,
+
+    /// Write docs grouped by slice in slice id order
+    ///
+    /// Pages of a slice are buffered in memory until all slices before it have finished, so a slow
+    /// slice may cause up to the whole remaining export to be held in memory.
+    #[structopt(long = "ordered")]
+    pub ordered: bool,
+
+    /// Progress display: bar, spinner, aggregate for a single bar of all slices, or plain for a periodic status line
+    /// which suits log files
+    #[structopt(long = "progress-style", alias = "progress", default_value = "bar")]
+    pub progress: BarStyle,
+
+    /// Progress refresh interval in milliseconds [default: 100, 10000 for plain]
+    #[structopt(long = "progress-refresh")]
+    pub progress_refresh: Option<u64>,
+
+    /// Log the docs and bytes written so far and the current rate to stderr at this interval, like `30s`
+    ///
+    /// Unlike the progress bars it suits log files, and shows whether the throughput degrades over a long pull.
+    #[structopt(long = "stats-interval")]
+    pub stats_interval: Option<Ttl>,
+
+    /// Run the pull but discard the docs instead of writing them, then report the throughput and the time of each slice
+    ///
+    /// It measures the cluster and the network apart from the disk and the compression, to compare settings like
+    /// --slice, --batch and --max-concurrent-requests. --output and --compress are ignored.
+    #[structopt(
+        long = "benchmark",
+        conflicts_with_all = &[
+            "output-template",
+            "verify",
+            "rotate-bytes",
+            "rotate-docs",
+            "watermark-file",
+            "diff-deleted"
+        ]
+    )]
+    pub benchmark: bool,
+
+    /// Stop the benchmark after this time, like `1m`, to measure a large index briefly
+    #[structopt(long = "benchmark-duration", requires = "benchmark")]
+    pub benchmark_duration: Option<Ttl>,
+
+    /// Show error responses of the cluster as their type, reason and root causes on lines of their own
+    #[structopt(long = "pretty-error")]
+    pub pretty_error: bool,
+
+    /// Before pulling, warn about fields whose type differs between the indices matching --index, like `logs-*`
+    #[structopt(long = "check-mappings")]
+    pub check_mappings: bool,
+
+    /// Log the start and the end of each slice
+    ///
+    /// At the end the time taken by each slice is printed, along with percentiles of the query time the cluster
+    /// reports for the pages and of the whole time of their requests.
+    #[structopt(short = "v", long = "verbose")]
+    pub verbose: bool,
+
+    /// Field tracked by --watermark-file, usually a time or sequence field which grows with new docs
+    #[structopt(long = "watermark-field", requires = "watermark-file")]
+    pub watermark_field: Option<String>,
+
+    /// File keeping the max --watermark-field value of the last export, only docs above it are pulled
+    ///
+    /// The file is created by the first export, which pulls all docs, and updated after each successful export. Docs
+    /// indexed later with a value at or below the saved one are never pulled, so it suits append-mostly indices.
+    #[structopt(long = "watermark-file", requires = "watermark-field")]
+    pub watermark_file: Option<PathBuf>,
+
+    /// Previous export to diff against by `_id`, only docs which are new or whose `_source` changed are written
+    ///
+    /// The previous export has to be of whole hits, as written with --with-seq-no or --diff-against, and may be
+    /// compressed. Repeat it for an export of several files. The previous docs are kept in memory by a hash of their
+    /// source.
+    #[structopt(long = "diff-against", number_of_values = 1)]
+    pub diff_against: Vec<PathBuf>,
+
+    /// File listing the docs of --diff-against which are gone, as json lines with their `_id`
+    ///
+    /// Docs left out by the query count as gone as well.
+    #[structopt(long = "diff-deleted", requires = "diff-against", conflicts_with_all = &["limit", "only-slice"])]
+    pub diff_deleted: Option<PathBuf>,
+}
+
+fn parse_param(s: &str) -> Result<(String, String), String> {
+    match s.find('=') {
+        Some(i) if i > 0 => Ok((s[..i].to_string(), s[i + 1..].to_string())),
+        _ => Err(format!("expect `key=value`, got `{}`", s)),
+    }
+}
+
+/// Parses a header like `X-Api-Key: secret`, checking the name and the value are valid in http.
+pub fn parse_header(s: &str) -> Result<(String, String), String> {
+    let (name, value) = match s.find(':') {
+        Some(i) => (s[..i].trim(), s[i + 1..].trim()),
+        None => return Err(format!("expect `Key: Value`, got `{}`", s)),
+    };
+    HeaderName::from_bytes(name.as_bytes()).map_err(|_| format!("invalid header name `{}`", name))?;
+    HeaderValue::from_str(value).map_err(|_| format!("invalid value of header `{}`", name))?;
+    Ok((name.to_string(), value.to_string()))
+}
+
+fn parse_positive(s: &str) -> Result<u64, String> {
+    match s.parse() {
+        Ok(0) | Err(_) => Err(format!("expect a positive number, got `{}`", s)),
+        Ok(n) => Ok(n),
+    }
+}
+
+/// Parses a size like `1024`, `64KB` or `1.5GB`, the units are multiples of 1024.
+fn parse_size(s: &str) -> Result<u64, String> {
+    let upper = s.trim().to_ascii_uppercase();
+    let i = upper
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(upper.len());
+    let (n, unit) = upper.split_at(i);
+    let scale: u64 = match unit.trim().trim_end_matches("IB").trim_end_matches('B') {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => return Err(format!("expect a size like `500MB`, got `{}`", s)),
+    };
+    match n.parse::<f64>() {
+        // the cast would saturate instead
+        Ok(n) if n * scale as f64 >= u64::MAX as f64 => Err(format!("size `{}` is too large", s)),
+        Ok(n) if n > 0.0 => Ok((n * scale as f64) as u64),
+        _ => Err(format!("expect a size like `500MB`, got `{}`", s)),
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum SliceCount {
+    Auto,
+    Fixed(u64),
+}
+
+impl FromStr for SliceCount {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(SliceCount::Auto),
+            s => match s.parse() {
+                Ok(0) | Err(_) => Err(format!("expect a positive number or `auto`, got `{}`", s)),
+                Ok(n) => Ok(SliceCount::Fixed(n)),
+            },
+        }
+    }
+}
+
+/// Shard ids selected by `--shards`, sorted and deduplicated.
+#[derive(Debug, Clone)]
+pub struct ShardSet(pub Vec<u64>);
+
+impl FromStr for ShardSet {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let id = |s: &str| {
+            s.trim()
+                .parse::<u64>()
+                .map_err(|_| format!("expect shard ids like `0-2` or `0,3,5`, got `{}`", s))
+        };
+        let mut ids = vec![];
+        for part in s.split(',') {
+            match part.find('-') {
+                Some(i) => {
+                    let (from, to) = (id(&part[..i])?, id(&part[i + 1..])?);
+                    if from > to {
+                        return Err(format!("invalid shard range `{}`", part));
+                    }
+                    ids.extend(from..=to);
+                }
+                None => ids.push(id(part)?),
+            }
+        }
+        ids.sort_unstable();
+        ids.dedup();
+        Ok(ShardSet(ids))
+    }
+}
+
+impl fmt::Display for ShardSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ids: Vec<_> = self.0.iter().map(|id| id.to_string()).collect();
+        f.write_str(&ids.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn size_units_are_multiples_of_1024() {
+        assert_eq!(parse_size("1024"), Ok(1024));
+        assert_eq!(parse_size("64KB"), Ok(64 << 10));
+        assert_eq!(parse_size("64kib"), Ok(64 << 10));
+        assert_eq!(parse_size("500 M"), Ok(500 << 20));
+        assert_eq!(parse_size("1.5GB"), Ok(3 << 29));
+        assert_eq!(parse_size("2T"), Ok(2 << 40));
+    }
+
+    #[test]
+    fn size_rejects_overflows_and_unknown_units() {
+        assert_eq!(
+            parse_size("16777216T"),
+            Err("size `16777216T` is too large".to_string())
+        );
+        assert_eq!(
+            parse_size("99999999999999999999"),
+            Err("size `99999999999999999999` is too large".to_string())
+        );
+        assert_eq!(parse_size("0"), Err("expect a size like `500MB`, got `0`".to_string()));
+        assert_eq!(
+            parse_size("5PB"),
+            Err("expect a size like `500MB`, got `5PB`".to_string())
+        );
+        assert_eq!(
+            parse_size("MB"),
+            Err("expect a size like `500MB`, got `MB`".to_string())
+        );
+    }
+
+    #[test]
+    fn shard_set_merges_ranges_and_ids() {
+        let ids = |s: &str| s.parse::<ShardSet>().unwrap().0;
+        assert_eq!(ids("3"), [3]);
+        assert_eq!(ids("0-2"), [0, 1, 2]);
+        assert_eq!(ids(" 5, 0-1 ,3-3"), [0, 1, 3, 5]);
+        // overlapping ranges and repeated ids are counted once
+        assert_eq!(ids("0-3,2-5,4"), [0, 1, 2, 3, 4, 5]);
+        assert_eq!("4,1-2".parse::<ShardSet>().unwrap().to_string(), "1,2,4");
+    }
+
+    #[test]
+    fn shard_set_rejects_reversed_ranges_and_empty_items() {
+        let error = |s: &str| s.parse::<ShardSet>().unwrap_err();
+        assert_eq!(error("5-2"), "invalid shard range `5-2`");
+        assert_eq!(error("1,,3"), "expect shard ids like `0-2` or `0,3,5`, got ``");
+        assert_eq!(error(""), "expect shard ids like `0-2` or `0,3,5`, got ``");
+        assert_eq!(error("2-"), "expect shard ids like `0-2` or `0,3,5`, got ``");
+        assert_eq!(error("a"), "expect shard ids like `0-2` or `0,3,5`, got `a`");
+    }
 }
@@ -0,0 +1,89 @@
+use crate::cli::AggOpt;
+use crate::cmd::{connect, load_query, Connection};
+use crate::output::{output_path, Encoding, Output, Rotation};
+use estunnel::common::Result;
+use estunnel::elastic::{describe_error, endpoint, request_elastic};
+use serde_json::{json, Value};
+
+pub fn agg(opt: AggOpt) -> Result<()> {
+    let AggOpt {
+        conn,
+        index,
+        query,
+        size,
+        output,
+        compress,
+        compress_level,
+        pretty_error,
+    } = opt;
+    if let Some(level) = compress_level {
+        compress.check_level(level)?;
+    }
+    let Connection {
+        client,
+        host,
+        user,
+        pass,
+        ..
+    } = connect(conn)?;
+    let mut query = load_query(query.as_deref())?;
+    let name = composite_agg(&query)?;
+    let aggs = if query["aggs"].is_object() {
+        "aggs"
+    } else {
+        "aggregations"
+    };
+    // only the buckets are wanted
+    query["size"] = json!(0);
+    if let Some(size) = size {
+        query[aggs][&name]["composite"]["size"] = json!(size);
+    }
+
+    let url = endpoint(&host, &format!("{}/_search", index));
+    let encoding = Encoding {
+        compression: compress,
+        level:       compress_level,
+        checksum:    None,
+    };
+    let mut output = Output::create(&output_path(output, compress), encoding, Rotation::default())?;
+    let mut total = 0u64;
+    loop {
+        let res: Value = request_elastic(&client, &url, &query, &user, &pass, &None)
+            .map_err(|e| format!("Fetch error: {}", describe_error(&*e, pretty_error)))?
+            .json()?;
+        let agg = &res["aggregations"][&name];
+        let buckets = agg["buckets"].as_array().map(Vec::as_slice).unwrap_or_default();
+        for bucket in buckets {
+            output.write_doc(bucket)?;
+        }
+        output.end_page()?;
+        total += buckets.len() as u64;
+        // the last page may still have an `after_key`, the next one is empty then
+        match &agg["after_key"] {
+            after if after.is_object() && !buckets.is_empty() => {
+                query[aggs][&name]["composite"]["after"] = after.clone()
+            }
+            _ => break,
+        }
+    }
+    output.finish()?;
+    eprintln!("{} buckets", total);
+    Ok(())
+}
+
+/// Returns the name of the composite aggregation at the top level of the query, which must be the only one.
+fn composite_agg(query: &Value) -> Result<String> {
+    let aggs = match query.get("aggs").or_else(|| query.get("aggregations")) {
+        Some(Value::Object(aggs)) => aggs,
+        _ => {
+            return Err(
+                "the query needs a composite aggregation like `{\"aggs\": {\"name\": {\"composite\": {...}}}}`".into(),
+            )
+        }
+    };
+    match aggs.iter().collect::<Vec<_>>().as_slice() {
+        [(name, agg)] if agg["composite"].is_object() => Ok(name.to_string()),
+        [(name, _)] => Err(format!("aggregation `{}` is not a composite aggregation", name).into()),
+        _ => Err("the query must have exactly one top level aggregation, which is composite".into()),
+    }
+}
@@ -1,5 +1,7 @@
-use crate::cli::{CompletionOpt, Opt};
-use crate::common::Result;
+use crate::cli::{CompleteIndicesOpt, CompletionOpt, Opt};
+use crate::cmd::{connect, Connection};
+use estunnel::common::Result;
+use estunnel::elastic::cat_indices;
 use structopt::StructOpt;
 
 pub fn completion(opt: CompletionOpt) -> Result<()> {
@@ -7,3 +9,29 @@ pub fn completion(opt: CompletionOpt) -> Result<()> {
     Opt::clap().gen_completions_to(env!("CARGO_PKG_NAME"), shell, &mut std::io::stdout());
     Ok(())
 }
+
+pub fn complete_indices(opt: CompleteIndicesOpt) -> Result<()> {
+    let CompleteIndicesOpt { conn, prefix } = opt;
+    let Connection {
+        client,
+        host,
+        user,
+        pass,
+        ..
+    } = connect(conn)?;
+    // the pattern of the prefix would match nothing after a comma of a list like `logs,met`
+    let (listed, word) = match prefix.rfind(',') {
+        Some(i) => prefix.split_at(i + 1),
+        None => ("", prefix.as_str()),
+    };
+    let mut names: Vec<_> = cat_indices(&client, &host, &format!("{}*", word), &user, &pass)?
+        .into_iter()
+        .map(|info| info.index)
+        .filter(|name| name.starts_with(word) && (word.starts_with('.') || !name.starts_with('.')))
+        .collect();
+    names.sort();
+    for name in names {
+        println!("{}{}", listed, name);
+    }
+    Ok(())
+}
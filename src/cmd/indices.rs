@@ -0,0 +1,39 @@
+use crate::cli::IndicesOpt;
+use crate::cmd::{connect, Connection};
+use estunnel::common::Result;
+use estunnel::elastic::cat_indices;
+
+pub fn indices(opt: IndicesOpt) -> Result<()> {
+    let IndicesOpt { conn, pattern } = opt;
+    let Connection {
+        client,
+        host,
+        user,
+        pass,
+        ..
+    } = connect(conn)?;
+    let mut indices = cat_indices(&client, &host, &pattern, &user, &pass)?;
+    indices.sort_by(|a, b| a.index.cmp(&b.index));
+
+    let width = indices.iter().map(|i| i.index.len()).max().unwrap_or(0).max(5);
+    println!(
+        "{:width$} {:>12} {:>10} {:>6}",
+        "Index",
+        "Documents",
+        "Size",
+        "Shards",
+        width = width
+    );
+    for i in indices {
+        // closed indices have no stats
+        println!(
+            "{:width$} {:>12} {:>10} {:>6}",
+            i.index,
+            i.docs_count.as_deref().unwrap_or("-"),
+            i.store_size.as_deref().unwrap_or("-"),
+            i.pri.as_deref().unwrap_or("-"),
+            width = width
+        );
+    }
+    Ok(())
+}
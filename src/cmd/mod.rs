@@ -1,3 +1,177 @@
+use crate::cli::{parse_header, ConnOpt};
+use crate::{sni, uds};
+use estunnel::common::Result;
+use estunnel::elastic::{build_client, PoolOptions};
+use estunnel::query::check_query;
+use reqwest::blocking::Client;
+use reqwest::Url;
+use serde_json::{json, Value};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, IsTerminal, Write};
+use std::path::Path;
+
+pub mod agg;
 pub mod completion;
+pub mod indices;
+pub mod probe;
 pub mod pull;
+pub mod reindex;
+pub mod run;
 pub mod update;
+pub mod validate;
+
+/// A client set up from the connection options, with the settings it was built from.
+#[derive(Clone)]
+pub struct Connection {
+    pub client:   Client,
+    pub host:     String,
+    pub no_proxy: bool,
+    /// Proxy the client goes through, like the tunnel of `--tls-sni`.
+    pub proxy:    Option<String>,
+    pub headers:  Vec<(String, String)>,
+    pub pool:     PoolOptions,
+    pub user:     Option<String>,
+    pub pass:     Option<String>,
+}
+
+pub fn connect(opt: ConnOpt) -> Result<Connection> {
+    let ConnOpt {
+        host,
+        no_proxy,
+        mut headers,
+        header_file,
+        tls_sni,
+        host_header,
+        pool_max_idle_per_host,
+        pool_idle_timeout,
+        user,
+    } = opt;
+    if let Some(path) = header_file {
+        let text = fs::read_to_string(&path)?;
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            headers.push(parse_header(line).map_err(|e| format!("{}:{}: {}", path.display(), i + 1, e))?);
+        }
+    }
+    let (user, pass) = userpass(user)?;
+    // the forwarding port is local, it must not go through a proxy
+    let no_proxy = no_proxy || host.starts_with("unix://");
+    // it closes each connection after one request, so connections are not kept for reuse
+    let pool_max_idle_per_host = match host.starts_with("unix://") {
+        true => Some(0),
+        false => pool_max_idle_per_host,
+    };
+    let host = match host.strip_prefix("unix://") {
+        Some(path) => {
+            let (url, token) = uds::forward(path.into())?;
+            headers.push((uds::TOKEN_HEADER.to_string(), token));
+            url
+        }
+        None => host,
+    };
+    let pool = PoolOptions {
+        max_idle_per_host: pool_max_idle_per_host,
+        idle_timeout:      pool_idle_timeout.map(|timeout| timeout.duration),
+    };
+    if let Some(name) = host_header {
+        headers.push(("Host".to_string(), name));
+    }
+    let (host, proxy) = match tls_sni {
+        Some(name) => {
+            let (host, addr) = sni_host(&host, &name)?;
+            (host, Some(sni::tunnel(addr)?))
+        }
+        None => (host, None),
+    };
+    let client = build_client(no_proxy, proxy.as_deref(), &headers, pool)?;
+    Ok(Connection {
+        client,
+        host,
+        no_proxy,
+        proxy,
+        headers,
+        pool,
+        user,
+        pass,
+    })
+}
+
+/// Replaces the host of the https url `host` with the server `name`, returning the new url and the address it had.
+fn sni_host(host: &str, name: &str) -> Result<(String, String)> {
+    let mut url = Url::parse(host).map_err(|e| format!("invalid host {}: {}", host, e))?;
+    if url.scheme() != "https" {
+        return Err(format!("--tls-sni needs an https host, got {}", host).into());
+    }
+    let addr = match (url.host_str(), url.port_or_known_default()) {
+        (Some(ip), Some(port)) => format!("{}:{}", ip, port),
+        _ => return Err(format!("invalid host {}", host).into()),
+    };
+    url.set_host(Some(name))
+        .map_err(|e| format!("invalid --tls-sni {}: {}", name, e))?;
+    Ok((url.to_string(), addr))
+}
+
+/// Loads the query body from the `-q/--query` value, which is either a file path or the json body itself.
+pub fn load_query(query: Option<&Path>) -> Result<Value> {
+    Ok(match query {
+        Some(body) if body.to_str().is_some_and(|s| s.trim_start().starts_with('{')) => {
+            let query = serde_json::from_str(body.to_str().unwrap())?;
+            check_query(&query)?;
+            query
+        }
+        Some(path) => {
+            let query = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+            check_query(&query).map_err(|e| format!("{}: {}", path.display(), e))?;
+            query
+        }
+        None => json!({ "query": { "match_all": {} } }),
+    })
+}
+
+/// Asks on the terminal whether to go on with what the message describes, errors unless the answer is yes.
+pub fn confirm(message: &str, assume_yes: bool) -> Result<()> {
+    if assume_yes {
+        return Ok(());
+    }
+    if !io::stdin().is_terminal() {
+        return Err(format!("{}, pass --assume-yes to go on without a terminal", message).into());
+    }
+    eprint!("{} - continue? [y/N] ", message);
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    match answer.trim().to_lowercase().as_str() {
+        "y" | "yes" => Ok(()),
+        _ => Err("aborted".into()),
+    }
+}
+
+/// Splits `--user` into the user and the password, prompting for a missing password. Without a user the requests are
+/// anonymous.
+fn userpass(auth: Option<String>) -> Result<(Option<String>, Option<String>)> {
+    match auth {
+        Some(auth) => {
+            let id = auth.find(':');
+            if let Some(id) = id {
+                let user = &auth[0..id];
+                let pass = &auth[id + 1..];
+                Ok((Some(user.to_string()), Some(pass.to_string())))
+            } else {
+                let prompt = format!("Enter host password for user {}: ", auth);
+                // fails without a controlling terminal, e.g. under nohup or cron
+                let pass = rpassword::read_password_from_tty(Some(&prompt)).map_err(|e| {
+                    format!(
+                        "no TTY available for the password prompt ({}), pass the password with \
+                         `--user {}:<password>` or `password_env` of a job file",
+                        e, auth
+                    )
+                })?;
+                Ok((Some(auth), Some(pass)))
+            }
+        }
+        None => Ok((None, None)),
+    }
+}
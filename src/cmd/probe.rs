@@ -0,0 +1,91 @@
+use crate::cli::ProbeOpt;
+use crate::cmd::{connect, Connection};
+use estunnel::common::Result;
+use estunnel::elastic::*;
+use serde_json::Value;
+
+/// Default `search.max_open_scroll_context` of clusters which do not report it.
+const DEFAULT_SCROLL_CONTEXTS: u64 = 500;
+/// Default `--batch` of `pull`.
+const DEFAULT_BATCH: u64 = 1000;
+
+pub fn probe(opt: ProbeOpt) -> Result<()> {
+    let ProbeOpt { conn, index } = opt;
+    let Connection {
+        client,
+        host,
+        user,
+        pass,
+        ..
+    } = connect(conn)?;
+    let version = cluster_version(&client, &host, &user, &pass)?;
+    let shards = shard_count(&client, &host, &index, None, &user, &pass)?;
+    let docs = doc_count(&client, &host, &index, &user, &pass)?;
+    let settings = index_settings(&client, &host, &index, &user, &pass)?;
+    let max_result_window = settings
+        .values()
+        .filter_map(|settings| settings.get("index.max_result_window").and_then(as_u64))
+        .min();
+    let frozen: Vec<_> = settings
+        .iter()
+        .filter(|(_, settings)| settings.get("index.frozen").and_then(Value::as_str) == Some("true"))
+        .map(|(index, _)| index.as_str())
+        .collect();
+    let scroll_contexts = cluster_setting(&client, &host, "search.max_open_scroll_context", &user, &pass)?
+        .as_ref()
+        .and_then(as_u64);
+
+    let show = |n: Option<u64>| n.map_or_else(|| "-".to_string(), |n| n.to_string());
+    println!("{:24} {}", "Version", version);
+    println!(
+        "{:24} {}",
+        "Point in time",
+        if supports_pit(&version) { "yes" } else { "no" }
+    );
+    println!("{:24} {}", "Indices", settings.len());
+    println!("{:24} {}", "Shards", shards);
+    println!("{:24} {}", "Documents", docs);
+    println!("{:24} {}", "max_result_window", show(max_result_window));
+    println!("{:24} {}", "max_open_scroll_context", show(scroll_contexts));
+    println!(
+        "{:24} {}",
+        "Frozen",
+        if frozen.is_empty() {
+            "no".to_string()
+        } else {
+            frozen.join(", ")
+        }
+    );
+
+    // one slice per shard, as long as the scroll contexts of the cluster suffice
+    let mut flags = vec![];
+    let slice = shards.min(scroll_contexts.unwrap_or(DEFAULT_SCROLL_CONTEXTS));
+    if slice > 1 {
+        flags.push(format!("--slice {}", slice));
+    }
+    if let Some(max) = max_result_window.filter(|&max| max < DEFAULT_BATCH) {
+        flags.push(format!("--batch {}", max));
+    }
+    // frozen indices are left out of searches unless asked for
+    if !frozen.is_empty() {
+        flags.push("--param ignore_throttled=false".to_string());
+    }
+    if !flags.is_empty() {
+        println!();
+        println!("Recommended: estunnel pull -i {} {}", index, flags.join(" "));
+    }
+    Ok(())
+}
+
+/// Settings are strings in flat settings, but numbers in some versions.
+fn as_u64(value: &Value) -> Option<u64> {
+    value.as_u64().or_else(|| value.as_str()?.parse().ok())
+}
+
+/// Whether the version has point in time searches, which came with 7.10.
+fn supports_pit(version: &str) -> bool {
+    let mut parts = version.split('.').map(|part| part.parse::<u64>().unwrap_or(0));
+    let major = parts.next().unwrap_or(0);
+    let minor = parts.next().unwrap_or(0);
+    (major, minor) >= (7, 10)
+}
@@ -1,303 +1,1390 @@
-use crate::cli::PullOpt;
-use crate::common::Result;
-use crate::elastic::*;
-use crossbeam::{crossbeam_channel, Receiver, Sender};
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use serde::export::fmt::Display;
+use crate::cli::{PullOpt, SliceCount};
+use crate::cmd::{confirm, connect, load_query, Connection};
+use crate::coerce::Coercion;
+use crate::diff::Diff;
+use crate::jq::Jq;
+use crate::output::{output_path, verify, Compression, Encoding, Format, Output, Rotation};
+use crate::partition::Partitions;
+use crate::progress::Progress;
+use crate::signal;
+use crate::transform::Transform;
+use crate::watermark::Watermark;
+use crossbeam::Receiver;
+use estunnel::common::{RequestLimit, Result};
+use estunnel::elastic::*;
+use estunnel::query::*;
+use estunnel::scroll::{self, ErrorFile, Observer, Page, PullConfig, Stats};
+use indicatif::{FormattedDuration, HumanBytes, ProgressBar};
 use serde_json::{json, Value};
 use std::cmp::{max, min};
-use std::fs::File;
-use std::io::{BufReader, BufWriter, Write};
-use std::path::PathBuf;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt::Display;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 use std::thread;
+use std::time::{Duration, Instant};
+
+/// Default upper bound of the scroll keep alive of a cluster.
+const MAX_KEEP_ALIVE: Duration = Duration::from_secs(24 * 3600);
 
 pub fn pull(opt: PullOpt) -> Result<()> {
+    // connected once, so a password is only prompted for once with a query directory
+    let conn = connect(opt.conn.clone())?;
+    if opt.clear_only {
+        confirm(
+            &format!(
+                "About to clear all open scroll contexts of host {}, including those of other clients",
+                opt.conn.host
+            ),
+            opt.assume_yes,
+        )?;
+        let freed = clear_all_scrolls(&conn.client, &conn.host, &conn.user, &conn.pass)
+            .map_err(|e| describe_error(&*e, opt.pretty_error))?;
+        eprintln!("{} scroll contexts cleared", freed);
+        return Ok(());
+    }
+    let opt = match &opt.index_file {
+        Some(_) if !opt.index.is_empty() => return Err("--index-file cannot be used with --index".into()),
+        Some(path) => PullOpt {
+            index: listed_indices(&conn, path, opt.pretty_error)?,
+            ..opt
+        },
+        None => opt,
+    };
+    match &opt.query {
+        Some(dir) if dir.is_dir() => pull_dir(&conn, dir.clone(), opt),
+        _ if opt.index_file.is_some() => pull_index(conn, PullOpt { per_index: true, ..opt }),
+        _ => pull_index(conn, opt),
+    }
+}
+
+/// Reads the index names of `path` and joins those that exist with commas, warning about the others.
+fn listed_indices(conn: &Connection, path: &Path, pretty_error: bool) -> Result<String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut indices = vec![];
+    for name in content.lines().map(str::trim) {
+        if name.is_empty() || name.starts_with('#') {
+            continue;
+        }
+        match cat_indices(&conn.client, &conn.host, name, &conn.user, &conn.pass) {
+            Ok(found) if !found.is_empty() => indices.push(name),
+            Ok(_) => eprintln!("Warning: index {} not found, skipped", name),
+            Err(e) if is_index_not_found(&*e) => eprintln!("Warning: index {} not found, skipped", name),
+            Err(e) => return Err(format!("{}: {}", name, describe_error(&*e, pretty_error)).into()),
+        }
+    }
+    if indices.is_empty() {
+        return Err(format!("no index of {} exists", path.display()).into());
+    }
+    Ok(indices.join(","))
+}
+
+/// Pulls each index matching --index with the query `<index>.json` of `dir`, one after another.
+fn pull_dir(conn: &Connection, dir: PathBuf, opt: PullOpt) -> Result<()> {
+    if !opt
+        .output_template
+        .as_ref()
+        .is_some_and(|template| template.has_index())
+    {
+        return Err("a query directory needs --output-template with an {index} placeholder".into());
+    }
+    let shared = [
+        ("--error-file", opt.error_file.is_some()),
+        ("--watermark-file", opt.watermark_file.is_some()),
+        ("--count-file", opt.count_file.is_some()),
+        ("--diff-deleted", opt.diff_deleted.is_some()),
+    ];
+    if let Some((name, _)) = shared.iter().find(|(_, set)| *set) {
+        return Err(format!(
+            "{} cannot be used with a query directory, as each index would overwrite it",
+            name
+        )
+        .into());
+    }
+    let mut queries = HashMap::new();
+    for entry in fs::read_dir(&dir)? {
+        let path = entry?.path();
+        if let (Some(index), Some("json")) = (path.file_stem(), path.extension().and_then(|ext| ext.to_str())) {
+            queries.insert(index.to_string_lossy().into_owned(), path);
+        }
+    }
+    let mut indices: Vec<_> = cat_indices(&conn.client, &conn.host, &opt.index, &conn.user, &conn.pass)
+        .map_err(|e| describe_error(&*e, opt.pretty_error))?
+        .into_iter()
+        .map(|info| info.index)
+        .collect();
+    indices.sort();
+    for index in &indices {
+        let query = match queries.remove(index) {
+            Some(query) => query,
+            None => {
+                eprintln!("Warning: no query for index {} in {}, skipped", index, dir.display());
+                continue;
+            }
+        };
+        eprintln!("Pulling {} with {}", index, query.display());
+        let opt = PullOpt {
+            index: index.clone(),
+            query: Some(query),
+            ..opt.clone()
+        };
+        pull_index(conn.clone(), opt).map_err(|e| format!("{}: {}", index, e))?;
+    }
+    let mut unused: Vec<_> = queries.into_values().collect();
+    unused.sort();
+    for query in unused {
+        eprintln!("Warning: {} matches no index of {}", query.display(), opt.index);
+    }
+    Ok(())
+}
+
+/// Pulls the index, or the indices matching it, with a single query.
+fn pull_index(conn: Connection, opt: PullOpt) -> Result<()> {
     let PullOpt {
-        host,
-        user,
+        conn: _,
         index,
         query,
+        time_field,
+        since,
+        until,
         slice,
+        per_index,
+        index_file: _,
+        only_slice,
+        shards,
+        routing,
         batch,
         limit,
         output,
+        output_template,
+        mkdir,
+        partition_by,
+        partition_format,
+        partition_max_open,
+        compress,
+        compress_level,
+        checksum,
+        rotate_bytes,
+        rotate_docs,
+        verify,
+        max_doc_bytes,
+        error_file,
+        coerce,
+        transforms,
+        mut params,
+        min_score,
+        search_timeout,
+        search_type,
+        no_partial_results,
+        fail_on_shard_failure,
+        fail_fast,
+        keep_going,
         ttl,
+        no_scroll_clear,
+        clear_only: _,
+        assume_yes: _,
+        fields,
+        docvalue_fields,
+        with_seq_no,
+        with_highlight,
+        with_inner_hits,
+        format,
+        bulk_index,
+        ordered,
+        max_scroll_contexts,
+        max_concurrent_requests,
+        max_retries,
+        retry_budget,
+        progress,
+        progress_refresh,
+        stats_interval,
+        benchmark,
+        benchmark_duration,
+        verbose,
+        watermark_field,
+        watermark_file,
+        diff_against,
+        diff_deleted,
+        count_file,
+        pretty_error,
+        check_mappings,
+        select,
     } = opt;
-    let (user, pass) = userpass(user);
+    let Connection {
+        client,
+        host,
+        no_proxy,
+        proxy,
+        headers,
+        pool,
+        user,
+        pass,
+    } = conn;
+
+    if ttl.duration > MAX_KEEP_ALIVE {
+        eprintln!(
+            "Warning: ttl {} exceeds the default search.max_keep_alive (24h) of the cluster, the search may be \
+             rejected",
+            ttl
+        );
+    }
+
+    if check_mappings {
+        let types = field_types(&client, &host, &index, &user, &pass).map_err(|e| describe_error(&*e, pretty_error))?;
+        warn_conflicts(&types);
+    }
+    if let Some(shards) = &shards {
+        let count =
+            shard_count(&client, &host, &index, None, &user, &pass).map_err(|e| describe_error(&*e, pretty_error))?;
+        if let Some(id) = shards.0.iter().find(|&&id| id >= count) {
+            return Err(format!("shard {} is out of range, the index has {} shards", id, count).into());
+        }
+        params.push(("preference".into(), format!("_shards:{}", shards)));
+    }
+    if let Some(routing) = &routing {
+        params.push(("routing".into(), routing.clone()));
+    }
+    // number of shards the search covers
+    let searched_shards = || match &shards {
+        Some(shards) => Ok(shards.0.len() as u64),
+        None => shard_count(&client, &host, &index, routing.as_deref(), &user, &pass)
+            .map_err(|e| describe_error(&*e, pretty_error)),
+    };
+    // with --per-index the slices are the indices, --slice of which are pulled at the same time
+    let (slice, indices, max_scroll_contexts) = match (per_index, slice) {
+        (false, slice) => (slice, vec![], max_scroll_contexts),
+        (true, SliceCount::Auto) => {
+            return Err("--per-index needs --slice to be the number of indices pulled at the same time".into())
+        }
+        (true, SliceCount::Fixed(workers)) => {
+            let mut indices: Vec<_> = cat_indices(&client, &host, &index, &user, &pass)
+                .map_err(|e| describe_error(&*e, pretty_error))?
+                .into_iter()
+                .map(|info| info.index)
+                .collect();
+            if indices.is_empty() {
+                return Err(format!("no index matches {}", index).into());
+            }
+            indices.sort();
+            let slice = SliceCount::Fixed(indices.len() as u64);
+            (
+                slice,
+                indices,
+                Some(max_scroll_contexts.map_or(workers, |max| max.min(workers))),
+            )
+        }
+    };
+    let slice = match slice {
+        SliceCount::Auto => searched_shards()?,
+        SliceCount::Fixed(slice) if !indices.is_empty() => slice,
+        SliceCount::Fixed(slice) if slice > 1 => {
+            match searched_shards() {
+                Ok(shards) if slice > shards => eprintln!(
+                    "Warning: you requested {} slices but the search covers {} shards; consider --slice {}",
+                    slice, shards, shards
+                ),
+                Ok(_) => {}
+                Err(e) => eprintln!("Warning: error getting shard count: {}", e),
+            }
+            slice
+        }
+        SliceCount::Fixed(slice) => slice,
+    };
+    if slice == 0 {
+        return Err(format!("the search of {} covers no shards, there is no slice to pull", index).into());
+    }
+    if let Some(id) = only_slice.iter().find(|&&id| id >= slice) {
+        return Err(format!("slice id {} is out of range, expect 0 to {}", id, slice - 1).into());
+    }
+    // ids of the slices this process pulls, in order so they match the outputs
+    let slice_ids: Vec<u64> = match only_slice.is_empty() {
+        true => (0..slice).collect(),
+        false => only_slice
+            .iter()
+            .copied()
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect(),
+    };
+    let slices = slice_ids.len() as u64;
+
+    if let Some(level) = compress_level {
+        compress.check_level(level)?;
+    }
+    let encoding = Encoding {
+        compression: compress,
+        level: compress_level,
+        checksum,
+    };
+    // the docs of --partition-by go to the partitions instead, the output of the slices is a sink
+    let partition_dir = match &partition_by {
+        Some(_) if output.starts_with("/dev") => {
+            return Err("--partition-by needs --output to be the directory of the partitions".into())
+        }
+        Some(_) if output.exists() && !output.is_dir() => {
+            return Err(format!("--partition-by needs a directory, {} is a file", output.display()).into())
+        }
+        Some(_) => Some(output.clone()),
+        None => None,
+    };
+    let outputs = match &output_template {
+        _ if partition_dir.is_some() => vec![PathBuf::new()],
+        Some(template) if indices.is_empty() => {
+            let paths = template.paths(&index, slice, compress);
+            slice_ids.iter().map(|&id| paths[id as usize].clone()).collect()
+        }
+        Some(template) => indices
+            .iter()
+            .enumerate()
+            .map(|(id, index)| template.paths(index, slice, compress).swap_remove(id))
+            .collect(),
+        None => vec![output_path(output, compress)],
+    };
+    let to_device = outputs.iter().any(|path| path.starts_with("/dev"));
+    // fail before any slice starts instead of after the first page from the output thread
+    for dir in outputs.iter().filter_map(|path| path.parent()).collect::<HashSet<_>>() {
+        if benchmark || dir.as_os_str().is_empty() || dir.is_dir() {
+            continue;
+        }
+        if !mkdir {
+            return Err(format!(
+                "output directory {} does not exist, use --mkdir to create it",
+                dir.display()
+            )
+            .into());
+        }
+        fs::create_dir_all(dir).map_err(|e| format!("cannot create output directory {}: {}", dir.display(), e))?;
+    }
+    if verify && to_device {
+        return Err("--verify needs the output to be a regular file".into());
+    }
+    if checksum.is_some() && to_device {
+        return Err("--checksum needs the output to be a regular file".into());
+    }
+    let rotation = Rotation {
+        bytes: rotate_bytes,
+        docs:  rotate_docs,
+    };
+    if rotation.enabled() && to_device {
+        return Err("--rotate-bytes and --rotate-docs need the output to be a regular file".into());
+    }
+
+    let bulk = match format {
+        Format::Bulk => {
+            let conflicts = [
+                ("--use-fields-api", !fields.is_empty()),
+                ("--docvalue-fields", !docvalue_fields.is_empty()),
+                ("--select", select.is_some()),
+                ("--with-highlight", with_highlight),
+                ("--with-inner-hits", with_inner_hits),
+            ];
+            if let Some((name, _)) = conflicts.iter().find(|(_, set)| *set) {
+                return Err(format!("--format bulk cannot be used with {}", name).into());
+            }
+            let version =
+                cluster_version(&client, &host, &user, &pass).map_err(|e| describe_error(&*e, pretty_error))?;
+            Some(Arc::new(Bulk {
+                index: bulk_index,
+                typed: needs_mapping_type(&version)?,
+            }))
+        }
+        Format::Json if bulk_index.is_some() => return Err("--bulk-index needs --format bulk".into()),
+        Format::Json => None,
+    };
 
-    let query: serde_json::Value = match query {
-        Some(query) => serde_json::from_reader(BufReader::new(File::open(query)?))?,
-        None => json!({ "query": { "match_all": {} } }),
+    let mut query = load_query(query.as_deref())?;
+    if since.is_some() || until.is_some() {
+        filter_time_range(&mut query, &time_field, since.as_deref(), until.as_deref());
+    }
+    if !fields.is_empty() {
+        query["fields"] = json!(fields);
+        query["_source"] = json!(false);
+    }
+    if !docvalue_fields.is_empty() {
+        query["docvalue_fields"] = json!(docvalue_fields);
+        query["_source"] = json!(false);
+    }
+    if with_seq_no {
+        query["seq_no_primary_term"] = json!(true);
+    }
+    if with_highlight && query.get("highlight").is_none() {
+        return Err("--with-highlight needs a `highlight` block in the query".into());
+    }
+    if with_inner_hits && !has_key(&query, "inner_hits") {
+        return Err("--with-inner-hits needs a `nested` or `has_child` query with `inner_hits`".into());
+    }
+    if let Some(min_score) = min_score {
+        query["min_score"] = json!(min_score);
+    }
+    if let Some(timeout) = search_timeout {
+        query["timeout"] = json!(timeout.to_string());
+    }
+    let kind = match (
+        with_seq_no || bulk.is_some() || !diff_against.is_empty(),
+        fields.is_empty() && docvalue_fields.is_empty(),
+    ) {
+        (true, _) => DocKind::Hit,
+        (false, false) => DocKind::Fields,
+        (false, true) => DocKind::Source,
+    };
+    let watermark = match (watermark_field, watermark_file) {
+        (Some(field), Some(path)) => {
+            if limit.is_some() {
+                return Err(
+                    "--watermark-file cannot be used with --limit, which may leave out docs below the max".into(),
+                );
+            }
+            let watermark = Watermark::load(&path, &field, kind == DocKind::Hit)
+                .map_err(|e| format!("{}: {}", path.display(), e))?;
+            if let Some(last) = watermark.last() {
+                add_filter(&mut query, json!({ "range": { field: { "gt": last } } }));
+            }
+            Some(Arc::new(watermark))
+        }
+        _ => None,
+    };
+    let partitions = match (partition_by, partition_dir) {
+        (Some(field), Some(dir)) => Some(Partitions::new(
+            field,
+            kind == DocKind::Hit,
+            partition_format,
+            dir,
+            encoding,
+            partition_max_open,
+        )),
+        _ => None,
     };
+    let undated = partitions.as_ref().map(|partitions| partitions.undated.clone());
+    let diff = match diff_against.is_empty() {
+        true => None,
+        false => Some(Arc::new(Diff::load(&diff_against)?)),
+    };
+    if let Some(search_type) = search_type {
+        params.push(("search_type".into(), search_type));
+    }
+    if no_partial_results {
+        params.push(("allow_partial_search_results".into(), "false".into()));
+    }
     let batch = match batch {
         Some(batch) => batch,
         None => query["size"].as_u64().unwrap_or(1000),
     };
     let batch = match limit {
-        Some(limit) => min(batch, max(limit / slice, 1)),
+        Some(limit) => min(batch, max(limit / slices, 1)),
         None => batch,
     };
 
-    let (res_tx, res_rx) = crossbeam_channel::bounded(slice as usize);
-    let (err_tx, err_rx) = crossbeam_channel::unbounded();
-    let task_finished = Arc::new(AtomicBool::new(false));
-
-    let (mpb, task_pb) = create_pb(limit);
-    let pool = threadpool::ThreadPool::new(slice as usize);
-
-    for slice_id in 0..slice {
-        pool.execute({
-            let res_tx = res_tx.clone();
-            let err_tx = err_tx.clone();
-            let mut query = query.clone();
-            let host = host.clone();
-            let index = index.clone();
-            let scroll_ttl = ttl.clone();
-            let user = user.clone();
-            let pass = pass.clone();
-            let task_finished = task_finished.clone();
-            let job_id = slice_id + 1;
-            let pb = create_pb_child(&slice, &mpb, &job_id);
-            move || {
-                let client = reqwest::blocking::Client::new();
-                query = inject_query(slice, slice_id, query);
-
-                let url = format!("{}/{}/_search", &host, &index);
-                let params = vec![("scroll", scroll_ttl.to_string()), ("size", batch.to_string())].into();
-                let res = request_elastic(&client, &url, &query, &user, &pass, &params);
-
-                let res = match res {
-                    Ok(res) => res,
-                    Err(e) => {
-                        err_tx.send(format!("Fetch error[{}]: {}", job_id, e))
-                            .expect("error sending to channel");
-                        pb.finish_at_current_pos();
-                        return;
-                    }
-                };
-
-                let (mut finished, mut scroll_id) = match parse_response(res) {
-                    Ok((docs, scroll_id, total)) => {
-                        let style = ProgressStyle::default_bar()
-                            .template("{prefix:.bold} {elapsed_precise} {bar:50} {percent:>3}% {pos}/{len} ETA {eta_precise} {msg:.yellow.bold}")
-                            .progress_chars("##-");
-                        pb.set_message("Running...");
-                        pb.set_style(style);
-
-                        let finished = docs.is_empty() || task_finished.load(Ordering::Relaxed);
-                        if !finished {
-                            send_docs(&res_tx, &pb, docs, total);
-                        }
-                        (finished, scroll_id)
-                    }
-                    Err(e) => {
-                        err_tx.send(format!("Parse error[{}]: {}", job_id, e))
-                            .expect("error sending to channel");
-                        pb.finish_at_current_pos();
-                        return;
-                    }
-                };
-
-                while !finished {
-                    let url = format!("{}/_search/scroll", &host);
-                    let query = json!({ "scroll": scroll_ttl, "scroll_id": scroll_id, });
-                    let res = request_elastic(&client, &url, &query, &user, &pass, &None);
-
-                    let res = match res {
-                        Ok(res) => res,
-                        Err(e) => {
-                            err_tx.send(format!("Error[{}]: {}", job_id, e))
-                                .expect("error sending to channel");
-                            pb.finish_at_current_pos();
-                            return;
-                        }
-                    };
-                    match parse_response(res) {
-                        Ok((docs, new_scroll_id, total)) => {
-                            finished = docs.is_empty() || task_finished.load(Ordering::Relaxed);
-                            scroll_id = new_scroll_id;
-                            if !finished {
-                                send_docs(&res_tx, &pb, docs, total);
-                            }
-                        }
-                        Err(e) => {
-                            err_tx.send(format!("Error[{}]: {}", job_id, e))
-                                .expect("error sending to channel");
-                            pb.finish_and_clear();
-                            return;
-                        }
-                    }
-                }
-                finish_pb(pb);
-            }
-        });
-    }
+    let error_file = match error_file {
+        Some(path) => Some(Arc::new(ErrorFile::create(&path)?)),
+        None => None,
+    };
+    let coerce = match coerce {
+        Some(path) => Some(Arc::new(Coerce {
+            coercion:   Coercion::load(&path).map_err(|e| format!("{}: {}", path.display(), e))?,
+            hits:       kind == DocKind::Hit,
+            error_file: error_file.clone(),
+            failed:     AtomicU64::new(0),
+        })),
+        None => None,
+    };
+    let transform = match transforms.is_empty() {
+        true => None,
+        false => Some(Arc::new(Transforms {
+            transforms,
+            hits: kind == DocKind::Hit,
+            error_file: error_file.clone(),
+            failed: AtomicU64::new(0),
+        })),
+    };
+    let select = match select {
+        Some(code) => Some(Arc::new(Select {
+            jq:         Jq::compile(&code)?,
+            error_file: error_file.clone(),
+            failed:     AtomicU64::new(0),
+        })),
+        None => None,
+    };
+    let config = PullConfig {
+        host,
+        no_proxy,
+        proxy,
+        headers,
+        pool,
+        index,
+        user,
+        pass,
+        query,
+        slice,
+        only_slices: slice_ids.clone(),
+        indices,
+        max_scroll_contexts,
+        request_limit: max_concurrent_requests.map(|max| Arc::new(RequestLimit::new(max))),
+        batch,
+        params,
+        ttl,
+        clear_scroll: !no_scroll_clear,
+        kind,
+        extras: Extras {
+            highlight:  with_highlight,
+            inner_hits: with_inner_hits,
+        },
+        fail_on_shard_failure,
+        fail_fast: fail_fast && !keep_going,
+        max_doc_bytes,
+        max_retries,
+        retry_budget,
+        error_file: error_file.clone(),
+        pretty_errors: pretty_error,
+    };
 
+    let mut pbs = Progress::new(progress, progress_refresh);
+    let task_pb = limit.map(|limit| pbs.add_task(limit));
+    let bars = Arc::new(pbs.add_only_slices(&slice_ids, slice, verbose));
+    let started = Instant::now();
+    let pull = scroll::start(config, bars.clone())?;
+    let stats = pull.stats.clone();
+
+    let hooks = DocHooks {
+        transform: transform.clone(),
+        coerce:    coerce.clone(),
+        watermark: watermark.clone(),
+        select:    select.clone(),
+        bulk:      bulk.clone(),
+        diff:      diff.clone(),
+        written:   Arc::new(Written::default()),
+    };
+    let written = hooks.written.clone();
+    let totals = hooks.written.clone();
+    let stop = pull.stop.clone();
     let output_thread = thread::spawn({
-        let err_tx = err_tx.clone();
-        move || match sink(limit, output, &res_rx, task_finished.clone(), &task_pb) {
+        let pages = pull.pages;
+        let stop = pull.stop;
+        move || match outputs
+            .iter()
+            .map(|path| match benchmark || partitions.is_some() {
+                true => Ok(Output::sink()),
+                false => Output::create(path, encoding, rotation),
+            })
+            .collect::<io::Result<Vec<_>>>()
+            .map_err(Into::into)
+            .and_then(|slices| {
+                let outputs = Outputs { slices, partitions };
+                sink(limit, outputs, ordered, &pages, stop.clone(), &task_pb, &hooks)
+            }) {
             Err(e) => {
-                task_finished.store(true, Ordering::Relaxed);
-                err_tx
-                    .send(format!("Write error: {}", e))
-                    .expect("error sending error to channel");
+                stop.store(true, Ordering::Relaxed);
                 if let Some(task_pb) = task_pb {
                     task_pb.finish_at_current_pos();
                 }
+                Err(format!("Write error: {}", e))
             }
-            Ok(curr) => {
+            Ok(sunk) => {
                 if let Some(task_pb) = task_pb {
-                    let style = ProgressStyle::default_bar().template(
-                        "{prefix:.bold} {elapsed_precise} {bar:50} {percent:>3}% {pos}/{len} ETA {eta_precise}",
-                    );
-                    task_pb.set_style(style);
+                    task_pb.set_style(progress.task_finished());
                     if let Some(limit) = limit {
-                        if curr >= limit {
+                        if sunk.docs >= limit {
                             task_pb.finish_with_message("Finished.")
                         } else {
                             task_pb.finish_at_current_pos();
                         }
                     }
                 }
+                Ok(sunk)
             }
         }
     });
 
-    thread::spawn(move || {
-        pool.join();
-        drop(res_tx);
+    let done = Arc::new(AtomicBool::new(false));
+    let count_thread = count_file.map(|path| {
+        let stats = stats.clone();
+        let done = done.clone();
+        thread::spawn(move || {
+            write_count_file(&path, &stats, slices, limit, &done).map_err(|e| format!("Count file error: {}", e))
+        })
+    });
+
+    let stats_thread = stats_interval.map(|interval| {
+        let done = done.clone();
+        thread::spawn(move || log_stats(&written, interval.duration, &done))
+    });
+
+    signal::handle_pause()?;
+    let pause_thread = thread::spawn({
+        let pause = pull.pause.clone();
+        let bars = bars.clone();
+        let done = done.clone();
+        move || watch_pause(&pause, &*bars, &done)
     });
 
-    mpb.join().expect("error joining progress threads");
+    let timer_thread = benchmark_duration.map(|duration| {
+        let done = done.clone();
+        // the slices end like with --limit once stopped
+        thread::spawn(move || {
+            while !done.load(Ordering::Relaxed) && started.elapsed() < duration.duration {
+                thread::sleep(Duration::from_millis(100));
+            }
+            stop.store(true, Ordering::Relaxed);
+        })
+    });
 
-    output_thread.join().unwrap();
-    drop(err_tx);
+    pbs.join(&stats.reported);
+    done.store(true, Ordering::Relaxed);
+    if let Some(timer_thread) = timer_thread {
+        timer_thread.join().unwrap();
+    }
+    pause_thread.join().unwrap();
+    if let Some(stats_thread) = stats_thread {
+        stats_thread.join().unwrap();
+    }
 
+    let written = output_thread.join().unwrap();
     // print error if any
-    for err in err_rx {
-        eprintln!("{}", err)
+    let mut failed = written.is_err();
+    if let Err(e) = count_thread.map_or(Ok(()), |count_thread| count_thread.join().unwrap()) {
+        eprintln!("{}", e);
+        failed = true;
+    }
+    for err in pull.errors {
+        eprintln!("{}", err);
+        failed = true;
+    }
+    if benchmark {
+        print_benchmark(started.elapsed(), &totals);
+    }
+    if verbose || benchmark {
+        bars.print_timings();
+        print_latency("Query time (took)", &stats.took.lock().unwrap());
+        print_latency("Request time", &stats.requests.lock().unwrap());
+    }
+    let Sunk {
+        docs: total,
+        files,
+        broken,
+    } = written.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        Sunk::default()
+    });
+    // docs after a failure or the reader going away are missing from the output
+    let complete = !failed && !broken;
+    eprintln!("{} documents", total);
+    let skipped = stats.skipped.load(Ordering::Relaxed);
+    if skipped > 0 {
+        eprintln!("{} documents skipped", skipped);
+    }
+    if let Some(transform) = &transform {
+        let failed = transform.failed.load(Ordering::Relaxed);
+        if failed > 0 {
+            eprintln!("{} documents failed --transform", failed);
+        }
+    }
+    if let Some(coerce) = &coerce {
+        let failed = coerce.failed.load(Ordering::Relaxed);
+        if failed > 0 {
+            eprintln!("{} documents failed coercion", failed);
+        }
+    }
+    if let Some(select) = &select {
+        let failed = select.failed.load(Ordering::Relaxed);
+        if failed > 0 {
+            eprintln!("{} documents failed --select", failed);
+        }
+    }
+    if let Some(undated) = &undated {
+        let undated = undated.load(Ordering::Relaxed);
+        if undated > 0 {
+            eprintln!(
+                "{} documents without a date, written to the __null__ partition",
+                undated
+            );
+        }
+    }
+    if let Some(diff) = &diff {
+        eprintln!("{} documents unchanged", diff.unchanged.load(Ordering::Relaxed));
+    }
+    if let Some(error_file) = &error_file {
+        error_file.flush()?;
+    }
+    let retries = stats.retries.load(Ordering::Relaxed);
+    if retries > 0 {
+        eprintln!("{} requests retried", retries);
+    }
+    let failed_slices = stats.failed.load(Ordering::Relaxed);
+    if failed_slices > 0 {
+        eprintln!("{} of {} slices failed", failed_slices, slices);
+    }
+    if let Some(watermark) = &watermark {
+        save_watermark(watermark, complete)?;
+    }
+
+    if let (Some(diff), Some(path)) = (&diff, &diff_deleted) {
+        // docs missing from an incomplete output would be listed as deleted
+        if !complete {
+            eprintln!("Warning: {} is not written as the output is incomplete", path.display());
+        } else {
+            let deleted = diff.save_deleted(path)?;
+            eprintln!("{} documents deleted, listed in {}", deleted, path.display());
+        }
+    }
+
+    if failed {
+        return Err("the pull failed, the output is incomplete".into());
+    }
+    if verify {
+        let reported = stats.reported.load(Ordering::Relaxed);
+        let expected = limit.map_or(reported, |limit| min(limit, reported));
+        let lines_per_doc = if bulk.is_some() { 2 } else { 1 };
+        verify_output(&files, compress, total, expected, lines_per_doc)?;
+    }
+    Ok(())
+}
+
+/// Writes the expected doc count to the file once all slices have reported their total, and again whenever it changes
+/// until `done`.
+fn write_count_file(path: &Path, stats: &Stats, slice: u64, limit: Option<u64>, done: &AtomicBool) -> io::Result<()> {
+    let mut written = None;
+    loop {
+        let finished = done.load(Ordering::Relaxed);
+        if finished || stats.started.load(Ordering::Relaxed) >= slice {
+            let reported = stats.reported.load(Ordering::Relaxed);
+            let expected = limit.map_or(reported, |limit| min(limit, reported));
+            if written != Some(expected) {
+                // replace the file at once, so a reader never sees it half written
+                let tmp = PathBuf::from(format!("{}.tmp", path.display()));
+                fs::write(&tmp, format!("{}\n", expected))?;
+                fs::rename(&tmp, path)?;
+                written = Some(expected);
+            }
+        }
+        if finished {
+            return Ok(());
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+/// Pauses the pull on SIGUSR1 and resumes it on SIGUSR2 until `done`.
+fn watch_pause(pause: &AtomicBool, observer: &dyn Observer, done: &AtomicBool) {
+    while !done.load(Ordering::Relaxed) {
+        let paused = signal::paused();
+        if pause.swap(paused, Ordering::Relaxed) != paused {
+            let msg = match paused {
+                true => "Paused after the current pages, send SIGUSR2 to resume",
+                false => "Resumed",
+            };
+            observer.log(0, msg);
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+/// Logs the totals of the output every `interval` until `done`.
+fn log_stats(written: &Written, interval: Duration, done: &AtomicBool) {
+    let started = Instant::now();
+    let mut last = (Instant::now(), 0);
+    while !done.load(Ordering::Relaxed) {
+        thread::sleep(Duration::from_millis(100));
+        if last.0.elapsed() < interval {
+            continue;
+        }
+        let docs = written.docs.load(Ordering::Relaxed);
+        let rate = (docs - last.1) as f64 / last.0.elapsed().as_secs_f64();
+        eprintln!(
+            "[{}] {} documents, {} written, {:.0} docs/s",
+            FormattedDuration(started.elapsed()),
+            docs,
+            HumanBytes(written.bytes.load(Ordering::Relaxed)),
+            rate
+        );
+        last = (Instant::now(), docs);
+    }
+}
+
+/// Prints the throughput of a benchmark, whose output is discarded.
+fn print_benchmark(elapsed: Duration, written: &Written) {
+    let docs = written.docs.load(Ordering::Relaxed);
+    let bytes = written.bytes.load(Ordering::Relaxed);
+    let secs = elapsed.as_secs_f64().max(f64::EPSILON);
+    eprintln!(
+        "Benchmark: {} documents, {} in {}, {:.0} docs/s, {}/s",
+        docs,
+        HumanBytes(bytes),
+        FormattedDuration(elapsed),
+        docs as f64 / secs,
+        HumanBytes((bytes as f64 / secs) as u64)
+    );
+}
+
+/// Whether the key appears anywhere in the json value.
+fn has_key(value: &Value, key: &str) -> bool {
+    match value {
+        Value::Object(map) => map.iter().any(|(k, v)| k == key || has_key(v, key)),
+        Value::Array(values) => values.iter().any(|v| has_key(v, key)),
+        _ => false,
+    }
+}
+
+/// Warns about fields which have different types in the indices.
+fn warn_conflicts(types: &FieldTypes) {
+    for (field, types) in types.iter().filter(|(_, types)| types.len() > 1) {
+        let types: Vec<_> = types
+            .iter()
+            .map(|(ty, indices)| match indices.as_slice() {
+                [index] => format!("{} in {}", ty, index),
+                [index, rest @ ..] => format!("{} in {} and {} more", ty, index, rest.len()),
+                [] => ty.clone(),
+            })
+            .collect();
+        eprintln!("Warning: field `{}` has conflicting types: {}", field, types.join(", "));
+    }
+}
+
+/// Prints percentiles of the milliseconds of the pages, a request time well above the query time points at the network
+/// or the output rather than the cluster.
+fn print_latency(name: &str, millis: &[u32]) {
+    if millis.is_empty() {
+        return;
     }
+    let mut millis = millis.to_vec();
+    millis.sort_unstable();
+    let percentile = |p: usize| millis[(millis.len() - 1) * p / 100];
+    eprintln!(
+        "{} of {} pages: p50 {}ms, p90 {}ms, p99 {}ms, max {}ms",
+        name,
+        millis.len(),
+        percentile(50),
+        percentile(90),
+        percentile(99),
+        millis[millis.len() - 1]
+    );
+}
 
+fn verify_output(
+    files: &[PathBuf],
+    compress: Compression,
+    written: u64,
+    expected: u64,
+    lines_per_doc: u64,
+) -> Result<()> {
+    let mut lines = 0;
+    let mut malformed = 0;
+    for file in files {
+        let res = verify(file, compress)?;
+        for (line, e) in res.malformed.iter().take(10) {
+            eprintln!("Malformed line {} of {}: {}", line, file.display(), e);
+        }
+        lines += res.lines;
+        malformed += res.malformed.len();
+    }
+    if expected != written {
+        eprintln!(
+            "Warning: the cluster reported {} documents but {} were written",
+            expected, written
+        );
+    }
+    if malformed > 0 || lines != written * lines_per_doc {
+        return Err(format!(
+            "verification failed: {} lines with {} malformed, {} documents written",
+            lines, malformed, written
+        )
+        .into());
+    }
+    eprintln!("Verified {} lines", lines);
     Ok(())
 }
 
+/// Writes the pulled docs to the output, or to the output of each slice if there are several, and returns their count
+/// along with the files written.
 fn sink<T: Display + ?Sized>(
     limit: Option<u64>,
-    output: PathBuf,
-    res_rx: &Receiver<Vec<Box<T>>>,
+    mut outputs: Outputs,
+    ordered: bool,
+    res_rx: &Receiver<Page<Box<T>>>,
     task_finished: Arc<AtomicBool>,
     task_pb: &Option<ProgressBar>,
-) -> Result<u64> {
-    let mut output = BufWriter::new(File::create(output)?);
+    hooks: &DocHooks,
+) -> Result<Sunk> {
     let mut curr = 0u64;
-    for docs in res_rx.iter() {
-        for doc in docs.iter() {
-            if let Some(limit) = limit {
-                if curr >= limit {
-                    task_finished.store(true, Ordering::Relaxed);
-                    return Ok(curr);
+    let res = write_pages(&mut outputs, ordered, limit, res_rx, &mut curr, task_pb, hooks);
+    let mut files: Vec<_> = outputs
+        .slices
+        .iter()
+        .flat_map(|output| output.files().to_vec())
+        .collect();
+    if let Some(partitions) = &outputs.partitions {
+        files.extend_from_slice(partitions.files());
+    }
+    // stop the workers in case the output quits early
+    task_finished.store(true, Ordering::Relaxed);
+    let res = res.and_then(|()| {
+        for output in outputs.slices {
+            output.finish()?;
+        }
+        match outputs.partitions {
+            Some(partitions) => partitions.finish(),
+            None => Ok(()),
+        }
+    });
+    match res {
+        Ok(()) => Ok(Sunk {
+            docs: curr,
+            files,
+            broken: false,
+        }),
+        // https://github.com/rust-lang/rust/issues/46016
+        // the reader went away like `| head`, which should just silently exit to avoid broken pipe error
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(Sunk {
+            docs: curr,
+            files,
+            broken: true,
+        }),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Docs written by the output thread and the files they went to.
+#[derive(Default)]
+struct Sunk {
+    docs:   u64,
+    files:  Vec<PathBuf>,
+    /// Whether the reader of the output went away before the end.
+    broken: bool,
+}
+
+/// Saves the max of the watermark field unless docs below it may be missing from the output.
+fn save_watermark(watermark: &Watermark, complete: bool) -> Result<()> {
+    // the next run has to pull the missing docs again
+    if !complete {
+        eprintln!("Warning: the watermark is not updated as the output is incomplete");
+    } else if let Some(value) = watermark.save()? {
+        eprintln!("Watermark updated to {}", value);
+    }
+    Ok(())
+}
+
+/// Writes docs of the pages until all slices are done or the limit is reached.
+fn write_pages<T: Display + ?Sized>(
+    outputs: &mut Outputs,
+    ordered: bool,
+    limit: Option<u64>,
+    res_rx: &Receiver<Page<Box<T>>>,
+    curr: &mut u64,
+    task_pb: &Option<ProgressBar>,
+    hooks: &DocHooks,
+) -> io::Result<()> {
+    // in ordered mode only docs of slice `next` are written immediately, pages of later slices are
+    // held back until all slices before them are done.
+    let mut next = 0u64;
+    let mut pending: HashMap<u64, Vec<Vec<Box<T>>>> = HashMap::new();
+    let mut done = HashSet::new();
+
+    for page in res_rx.iter() {
+        match page {
+            Page::Docs(slice_id, docs) if ordered && slice_id != next => {
+                pending.entry(slice_id).or_default().push(docs);
+                continue;
+            }
+            Page::Docs(slice_id, docs) => {
+                let output = output_of(&mut outputs.slices, slice_id);
+                let more = write_docs(output, outputs.partitions.as_mut(), &docs, limit, curr, task_pb, hooks)?;
+                output.end_page()?;
+                hooks.written.update(*curr, outputs);
+                if !more {
+                    return Ok(());
                 }
             }
-            match writeln!(&mut output, "{}", doc) {
-                Ok(_) => {
-                    curr += 1;
-                    if let Some(pb) = &task_pb {
-                        pb.inc(1)
-                    }
+            Page::Done(slice_id) => {
+                done.insert(slice_id);
+            }
+        }
+        while ordered && done.contains(&next) {
+            next += 1;
+            for docs in pending.remove(&next).unwrap_or_default() {
+                let output = output_of(&mut outputs.slices, next);
+                let more = write_docs(output, outputs.partitions.as_mut(), &docs, limit, curr, task_pb, hooks)?;
+                output.end_page()?;
+                hooks.written.update(*curr, outputs);
+                if !more {
+                    return Ok(());
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Outputs of the slices, and the partitions of --partition-by which get the docs instead if any.
+struct Outputs {
+    slices:     Vec<Output>,
+    partitions: Option<Partitions>,
+}
+
+/// Returns the output of the slice, or the only output.
+fn output_of(outputs: &mut [Output], slice_id: u64) -> &mut Output {
+    match outputs.len() {
+        1 => &mut outputs[0],
+        _ => &mut outputs[slice_id as usize],
+    }
+}
+
+/// Writes docs to output and returns whether the limit is not reached yet.
+fn write_docs<T: Display + ?Sized>(
+    output: &mut Output,
+    mut partitions: Option<&mut Partitions>,
+    docs: &[Box<T>],
+    limit: Option<u64>,
+    curr: &mut u64,
+    task_pb: &Option<ProgressBar>,
+    hooks: &DocHooks,
+) -> io::Result<bool> {
+    for doc in docs.iter() {
+        if let Some(limit) = limit {
+            if *curr >= limit {
+                return Ok(false);
+            }
+        }
+        let plain = hooks.transform.is_none()
+            && hooks.coerce.is_none()
+            && hooks.watermark.is_none()
+            && hooks.select.is_none()
+            && hooks.bulk.is_none()
+            && hooks.diff.is_none()
+            && partitions.is_none();
+        let lines = if plain {
+            output.write_doc(&**doc)?;
+            1
+        } else {
+            let mut value: Value = serde_json::from_str(&doc.to_string())?;
+            if let Some(transform) = &hooks.transform {
+                if !transform.apply(&mut value)? {
+                    continue;
+                }
+            }
+            if let Some(coerce) = &hooks.coerce {
+                if !coerce.apply(&mut value)? {
+                    continue;
                 }
-                Err(_e) => {
-                    task_finished.store(true, Ordering::Relaxed);
-                    // https://github.com/rust-lang/rust/issues/46016
-                    // should just silently exit in this case to avoid broken pipe error
-                    return Ok(curr);
-                    // return Err(Box::new(e));
+            }
+            if let Some(watermark) = &hooks.watermark {
+                watermark.observe(&value);
+            }
+            if let Some(diff) = &hooks.diff {
+                if !diff.changed(&value) {
+                    continue;
                 }
+            }
+            let output = match partitions.as_deref_mut() {
+                Some(partitions) => partitions.output(&value)?,
+                None => &mut *output,
             };
+            // the raw doc is written unless it has been changed
+            let changed = hooks.transform.is_some() || hooks.coerce.is_some();
+            match (&hooks.select, &hooks.bulk, changed) {
+                (Some(select), _, _) => {
+                    let values = select.apply(value)?;
+                    // each selected value counts against the limit, so only those it still has room for are written
+                    let room = limit.map_or(values.len() as u64, |limit| limit.saturating_sub(*curr));
+                    for value in values.iter().take(room as usize) {
+                        output.write_doc(value)?;
+                    }
+                    min(room, values.len() as u64)
+                }
+                // written at once, so rotation never separates the action from its source
+                (None, Some(bulk), changed) => {
+                    let hit = match changed {
+                        true => bulk.pair(&value.to_string())?,
+                        false => bulk.pair(&doc.to_string())?,
+                    };
+                    output.write_doc(&hit)?;
+                    1
+                }
+                (None, None, true) => {
+                    output.write_doc(&value)?;
+                    1
+                }
+                (None, None, false) => {
+                    output.write_doc(&**doc)?;
+                    1
+                }
+            }
+        };
+        *curr += lines;
+        if let Some(pb) = &task_pb {
+            pb.inc(lines)
         }
     }
-    Ok(curr)
+    Ok(true)
+}
+
+/// Work done on each doc by the output thread besides writing it.
+struct DocHooks {
+    transform: Option<Arc<Transforms>>,
+    coerce:    Option<Arc<Coerce>>,
+    watermark: Option<Arc<Watermark>>,
+    select:    Option<Arc<Select>>,
+    bulk:      Option<Arc<Bulk>>,
+    diff:      Option<Arc<Diff>>,
+    written:   Arc<Written>,
 }
 
-fn send_docs<T>(tx: &Sender<Vec<T>>, pb: &ProgressBar, docs: Vec<T>, total: u64) {
-    let len = docs.len() as u64;
-    tx.send(docs).expect("error sending to channel");
-    pb.set_length(total);
-    pb.inc(len);
+/// Totals of the output so far, updated after each page.
+#[derive(Default)]
+struct Written {
+    docs:  AtomicU64,
+    /// Bytes before compression.
+    bytes: AtomicU64,
 }
 
-fn inject_query(slice: u64, slice_id: u64, mut query: Value) -> Value {
-    if slice > 1 {
-        let obj = query.as_object_mut().unwrap();
-        obj.insert(
-            "slice".into(),
-            json!({
-                "id": slice_id,
-                "max": slice
-            }),
+impl Written {
+    fn update(&self, docs: u64, outputs: &Outputs) {
+        let partitions = outputs.partitions.as_ref().map_or(0, Partitions::total_bytes);
+        self.docs.store(docs, Ordering::Relaxed);
+        self.bytes.store(
+            outputs.slices.iter().map(Output::total_bytes).sum::<u64>() + partitions,
+            Ordering::Relaxed,
         );
-        return json!(obj);
     }
-    query
 }
 
-fn create_pb_child(slice: &u64, mpb: &Arc<MultiProgress>, job_id: &u64) -> ProgressBar {
-    let mpb = mpb.clone();
-    let pb = mpb.add(ProgressBar::new(1));
-    let style = ProgressStyle::default_bar()
-        .template("{prefix:.bold} {elapsed_precise} {bar:50} {percent:>3}% {msg:.yellow.bold}")
-        .progress_chars("##-");
-    pb.set_style(style);
-    let slice_num_width = slice.to_string().len();
-    pb.set_prefix(&format!("[{:0width$}/{}]", job_id, slice, width = slice_num_width));
-    pb.set_message("Starting...");
-    pb.set_draw_delta(1_000_000);
-    pb.enable_steady_tick(100);
-    pb
+/// Turns hits into the lines of a `_bulk` request indexing them.
+struct Bulk {
+    /// Index of the actions, the index of each hit if none.
+    index: Option<String>,
+    /// Whether the actions have the mapping type of each hit, as the cluster is older than 7.
+    typed: bool,
 }
 
-fn create_pb(limit: Option<u64>) -> (Arc<MultiProgress>, Option<ProgressBar>) {
-    let mpb = Arc::new(MultiProgress::new());
-    let task_pb = limit.map(|limit| {
-        let pb = mpb.add(ProgressBar::new(limit));
-        let style = ProgressStyle::default_bar()
-            .template("{prefix:.blue.bold} {elapsed_precise} {bar:50} {percent:>3}% {pos}/{len} ETA {eta_precise}");
-        pb.set_prefix("Task:");
-        pb.set_style(style);
-        pb.set_length(limit);
-        pb.set_draw_delta(1_000_000);
-        pb.enable_steady_tick(100);
-        pb
-    });
-    (mpb, task_pb)
+impl Bulk {
+    /// Returns the action line and the source line of the hit, whose source is kept as it is.
+    fn pair(&self, hit: &str) -> io::Result<String> {
+        let hit: Hit = serde_json::from_str(hit)?;
+        let source = hit
+            ._source
+            .as_ref()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("hit {} has no _source", hit._id)))?;
+        let index = self.index.as_deref().unwrap_or(&hit._index);
+        let doc_type = match (self.typed, &hit._type) {
+            (true, Some(doc_type)) => format!(",\"_type\":{}", json!(doc_type)),
+            (true, None) => ",\"_type\":\"_doc\"".to_string(),
+            (false, _) => String::new(),
+        };
+        let routing = match &hit._routing {
+            Some(routing) => format!(",\"routing\":{}", json!(routing)),
+            None => String::new(),
+        };
+        Ok(format!(
+            "{{\"index\":{{\"_index\":{}{},\"_id\":{}{}}}}}\n{}",
+            json!(index),
+            doc_type,
+            json!(hit._id),
+            routing,
+            source.get()
+        ))
+    }
 }
 
-fn finish_pb(pb: ProgressBar) {
-    let style = ProgressStyle::default_bar()
-        .template(
-            "{prefix:.bold} {elapsed_precise} {bar:50} {percent:>3}% {pos}/{len} ETA {eta_precise} {msg:.green.bold}",
-        )
-        .progress_chars("##-");
-    pb.set_length(pb.position()); // adjust length
-    pb.set_style(style);
-    pb.finish_with_message("Finished.");
+/// Transform of the docs by a jq filter before they are written.
+struct Select {
+    jq:         Jq,
+    error_file: Option<Arc<ErrorFile>>,
+    /// Count of docs skipped for failing the filter.
+    failed:     AtomicU64,
 }
 
-fn userpass(auth: Option<String>) -> (String, Option<String>) {
-    match auth {
-        Some(auth) => {
-            let id = auth.find(':');
-            if let Some(id) = id {
-                let user = &auth[0..id];
-                let pass = &auth[id + 1..];
-                (user.to_string(), Some(pass.to_string()))
-            } else {
-                let prompt = format!("Enter host password for user {}: ", auth);
-                let pass = rpassword::read_password_from_tty(Some(&prompt)).unwrap();
-                (auth, Some(pass))
-            }
+impl Select {
+    /// Returns the docs the filter turns the doc into, or records the doc as failed and returns none.
+    fn apply(&self, doc: Value) -> io::Result<Vec<Value>> {
+        let reason = match self.jq.run(doc.clone()) {
+            Ok(values) => return Ok(values),
+            Err(e) => format!("--select failed: {}", e),
+        };
+        self.failed.fetch_add(1, Ordering::Relaxed);
+        match &self.error_file {
+            Some(error_file) => error_file.record(&reason, &doc)?,
+            None => eprintln!("Warning: skipped a document, {}", reason),
+        }
+        Ok(vec![])
+    }
+}
+
+/// Built-in transforms of fields of the docs before they are coerced.
+struct Transforms {
+    transforms: Vec<Transform>,
+    /// Whether docs are whole hits, whose fields are in `_source`.
+    hits:       bool,
+    error_file: Option<Arc<ErrorFile>>,
+    /// Count of docs skipped for failing a transform.
+    failed:     AtomicU64,
+}
+
+impl Transforms {
+    /// Transforms the doc and returns true, or records the doc as failed and returns false.
+    fn apply(&self, doc: &mut Value) -> io::Result<bool> {
+        let root = match self.hits {
+            true => doc.get_mut("_source"),
+            false => Some(&mut *doc),
+        };
+        let res = root.map_or(Ok(()), |root| {
+            self.transforms.iter().try_for_each(|transform| transform.apply(root))
+        });
+        let reason = match res {
+            Ok(()) => return Ok(true),
+            Err(reason) => reason,
+        };
+        self.failed.fetch_add(1, Ordering::Relaxed);
+        match &self.error_file {
+            Some(error_file) => error_file.record(&reason, &doc)?,
+            None => eprintln!("Warning: skipped a document, {}", reason),
+        }
+        Ok(false)
+    }
+}
+
+/// Coercion of the docs before they are written.
+struct Coerce {
+    coercion:   Coercion,
+    /// Whether docs are whole hits, whose declared fields are in `_source`.
+    hits:       bool,
+    error_file: Option<Arc<ErrorFile>>,
+    /// Count of docs skipped for failing coercion.
+    failed:     AtomicU64,
+}
+
+impl Coerce {
+    /// Coerces the doc and returns true, or records the doc as failed and returns false.
+    fn apply(&self, doc: &mut Value) -> io::Result<bool> {
+        let root = match self.hits {
+            true => doc.get_mut("_source"),
+            false => Some(&mut *doc),
+        };
+        let reason = match root.map_or(Ok(()), |root| self.coercion.apply(root)) {
+            Ok(()) => return Ok(true),
+            Err(reason) => reason,
+        };
+        self.failed.fetch_add(1, Ordering::Relaxed);
+        match &self.error_file {
+            Some(error_file) => error_file.record(&reason, &doc)?,
+            None => eprintln!("Warning: skipped a document, {}", reason),
         }
-        None => ("estunnel".to_string(), None),
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::value::RawValue;
+
+    #[test]
+    fn bulk_pair_has_the_type_only_for_typed_clusters() {
+        let hit = r#"{"_index":"logs","_type":"event","_id":"1","_source":{"n":1}}"#;
+        let untyped = r#"{"_index":"logs","_id":"2","_source":{"n":2}}"#;
+        let typed = Bulk {
+            index: None,
+            typed: true,
+        };
+        assert_eq!(
+            typed.pair(hit).unwrap(),
+            "{\"index\":{\"_index\":\"logs\",\"_type\":\"event\",\"_id\":\"1\"}}\n{\"n\":1}"
+        );
+        assert_eq!(
+            typed.pair(untyped).unwrap(),
+            "{\"index\":{\"_index\":\"logs\",\"_type\":\"_doc\",\"_id\":\"2\"}}\n{\"n\":2}"
+        );
+        let bulk = Bulk {
+            index: Some("copy".into()),
+            typed: false,
+        };
+        assert_eq!(
+            bulk.pair(hit).unwrap(),
+            "{\"index\":{\"_index\":\"copy\",\"_id\":\"1\"}}\n{\"n\":1}"
+        );
+    }
+
+    #[test]
+    fn failed_output_leaves_the_watermark() {
+        let dir = std::env::temp_dir().join(format!("estunnel-watermark-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("watermark.json");
+        let saved = r#"{"field":"n","value":1}"#;
+        fs::write(&path, saved).unwrap();
+        let watermark = Arc::new(Watermark::load(&path, "n", false).unwrap());
+        let hooks = DocHooks {
+            transform: None,
+            coerce:    None,
+            watermark: Some(watermark.clone()),
+            select:    None,
+            bulk:      None,
+            diff:      None,
+            written:   Arc::new(Written::default()),
+        };
+        let (tx, rx) = crossbeam::unbounded();
+        let docs = (2..5)
+            .map(|n| RawValue::from_string(json!({ "n": n }).to_string()).unwrap())
+            .collect();
+        tx.send(Page::Docs(0, docs)).unwrap();
+        tx.send(Page::Done(0)).unwrap();
+        drop(tx);
+        let encoding = Encoding {
+            compression: Compression::None,
+            level:       None,
+            checksum:    None,
+        };
+        // writes to /dev/full fail like to a full disk
+        let outputs = Outputs {
+            slices:     vec![Output::create(Path::new("/dev/full"), encoding, Default::default()).unwrap()],
+            partitions: None,
+        };
+
+        let sunk = sink(
+            None,
+            outputs,
+            false,
+            &rx,
+            Arc::new(AtomicBool::new(false)),
+            &None,
+            &hooks,
+        );
+        assert!(sunk.is_err());
+        save_watermark(&watermark, sunk.is_ok_and(|sunk| !sunk.broken)).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), saved);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn selected_values_stop_at_the_limit() {
+        let path = std::env::temp_dir().join(format!("estunnel-limit-{}.json", std::process::id()));
+        let hooks = DocHooks {
+            transform: None,
+            coerce:    None,
+            watermark: None,
+            select:    Some(Arc::new(Select {
+                jq:         Jq::compile(".n, .n").unwrap(),
+                error_file: None,
+                failed:     AtomicU64::new(0),
+            })),
+            bulk:      None,
+            diff:      None,
+            written:   Arc::new(Written::default()),
+        };
+        let docs: Vec<_> = (1..4)
+            .map(|n| RawValue::from_string(json!({ "n": n }).to_string()).unwrap())
+            .collect();
+        let encoding = Encoding {
+            compression: Compression::None,
+            level:       None,
+            checksum:    None,
+        };
+        let mut output = Output::create(&path, encoding, Default::default()).unwrap();
+        let mut curr = 0;
+
+        let more = write_docs(&mut output, None, &docs, Some(3), &mut curr, &None, &hooks).unwrap();
+        output.finish().unwrap();
+        assert!(!more);
+        assert_eq!(curr, 3);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "1\n1\n2\n");
+        fs::remove_file(&path).unwrap();
     }
 }
@@ -0,0 +1,340 @@
+use crate::cli::{ConnOpt, ReindexOpt};
+use crate::cmd::{confirm, connect, load_query, Connection};
+use crate::date::{self, Date, DateFormat};
+use crate::progress::Progress;
+use crossbeam::Receiver;
+use estunnel::common::{RequestLimit, Result};
+use estunnel::elastic::{bulk, cluster_version, needs_mapping_type, query_count, DocKind, Hit, Source};
+use estunnel::scroll::{self, Page, PullConfig};
+use serde_json::value::RawValue;
+use serde_json::{json, Value};
+use std::mem;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread;
+
+pub fn reindex(opt: ReindexOpt) -> Result<()> {
+    let ReindexOpt {
+        from_host,
+        from_user,
+        to_host,
+        to_user,
+        no_proxy,
+        index,
+        dest_index,
+        dest_index_template,
+        dest_date_field,
+        dest_date_format,
+        dest_fallback_index,
+        query,
+        slice,
+        batch,
+        bulk_size,
+        refresh,
+        pipeline,
+        op_type,
+        max_concurrent_requests,
+        ttl,
+        progress,
+        assume_yes,
+    } = opt;
+    let conn = |host, user| ConnOpt {
+        host,
+        no_proxy,
+        headers: vec![],
+        header_file: None,
+        tls_sni: None,
+        host_header: None,
+        pool_max_idle_per_host: None,
+        pool_idle_timeout: None,
+        user,
+    };
+    let dated = match (dest_index_template, dest_fallback_index) {
+        (Some(template), Some(fallback)) => {
+            if !template.contains("{date}") {
+                return Err(format!("--dest-index-template needs a {{date}}, got `{}`", template).into());
+            }
+            Some(DatedIndex {
+                template,
+                field: dest_date_field,
+                format: dest_date_format,
+                fallback,
+            })
+        }
+        _ => None,
+    };
+    // kept for the summary, as the writer takes the template
+    let fallback = dated
+        .as_ref()
+        .map(|dated| (dated.field.clone(), dated.fallback.clone()));
+    let mut params = vec![];
+    if let Some(refresh) = refresh {
+        params.push(("refresh".to_string(), refresh));
+    }
+    if let Some(pipeline) = pipeline {
+        params.push(("pipeline".to_string(), pipeline));
+    }
+    // named as given for the confirmation, a unix socket is connected through a local port
+    let (from, to) = (from_host.clone(), to_host.clone());
+    let source = connect(conn(from_host, from_user))?;
+    let dest = connect(conn(to_host, to_user))?;
+    let version = cluster_version(&dest.client, &dest.host, &dest.user, &dest.pass)?;
+    let typed = needs_mapping_type(&version)?;
+    let query = load_query(query.as_deref())?;
+    let docs = query_count(&source.client, &source.host, &index, &query, &source.user, &source.pass)?;
+    let dest_desc = match (&dated, &dest_index) {
+        (Some(dated), _) => format!(
+            "indices {} by the date in {} ({} for docs without one)",
+            dated.template, dated.field, dated.fallback
+        ),
+        (None, Some(dest_index)) => format!("index {}", dest_index),
+        (None, None) => "indices of the same names".to_string(),
+    };
+    confirm(
+        &format!(
+            "About to {} {} docs of index {} at {} into {} at host {} (version {})",
+            op_type, docs, index, from, dest_desc, to, version
+        ),
+        assume_yes,
+    )?;
+
+    let mut config = PullConfig::new(source.host, index);
+    config.no_proxy = source.no_proxy;
+    config.proxy = source.proxy;
+    // with the token of a `unix://` host
+    config.headers = source.headers;
+    config.user = source.user;
+    config.pass = source.pass;
+    config.query = query;
+    config.slice = slice;
+    config.batch = batch;
+    config.ttl = ttl;
+    // the bulk requests take their turn with the scroll requests
+    let limit = max_concurrent_requests.map(|max| Arc::new(RequestLimit::new(max)));
+    config.request_limit = limit.clone();
+    // the action line needs the id of each doc
+    config.kind = DocKind::Hit;
+
+    let mut pbs = Progress::new(progress, None);
+    let bars = Arc::new(pbs.add_slices(slice, false));
+    let pull = scroll::start(config, bars)?;
+    let stats = pull.stats.clone();
+
+    let writer = thread::spawn({
+        let pages = pull.pages;
+        let stop = pull.stop;
+        move || {
+            let target = Target {
+                dest: &dest,
+                index: dest_index.as_deref(),
+                dated: dated.as_ref(),
+                op_type: &op_type,
+                typed,
+                params,
+                bulk_size: bulk_size.max(1),
+                limit: limit.as_deref(),
+            };
+            let res = push(&target, &pages);
+            // stop the workers in case the destination fails
+            stop.store(true, Ordering::Relaxed);
+            res.map_err(|e| format!("Bulk error: {}", e))
+        }
+    });
+
+    pbs.join(&stats.reported);
+    let pushed = writer.join().unwrap();
+    let mut failed = false;
+    for err in pull.errors {
+        eprintln!("{}", err);
+        failed = true;
+    }
+    let pushed = pushed?;
+    eprintln!("{} documents indexed", pushed.indexed);
+    if let (Some((field, index)), true) = (fallback, pushed.undated > 0) {
+        eprintln!(
+            "{} documents have no date in {} and went to {}",
+            pushed.undated, field, index
+        );
+    }
+    if pushed.existing > 0 {
+        eprintln!("{} documents exist in the destination and are kept", pushed.existing);
+    }
+    if let Some(reason) = pushed.first_failure {
+        return Err(format!(
+            "{} documents failed to index, the first because of {}",
+            pushed.failed, reason
+        )
+        .into());
+    }
+    if failed {
+        return Err(format!(
+            "{} of {} slices failed, the destination is incomplete",
+            stats.failed.load(Ordering::Relaxed),
+            slice
+        )
+        .into());
+    }
+    Ok(())
+}
+
+#[derive(Default)]
+struct Pushed {
+    indexed:       u64,
+    /// Docs kept in the destination by `create` as they exist there.
+    existing:      u64,
+    failed:        u64,
+    first_failure: Option<String>,
+    /// Docs sent to the fallback index for want of a date.
+    undated:       u64,
+}
+
+/// Destination indices named by the date of each doc.
+struct DatedIndex {
+    /// Index name with a `{date}`.
+    template: String,
+    field:    String,
+    format:   DateFormat,
+    /// Index of the docs without a date.
+    fallback: String,
+}
+
+impl DatedIndex {
+    /// Index of the doc, none if it has no date.
+    fn index(&self, source: &RawValue) -> Result<Option<String>> {
+        let source: Value = serde_json::from_str(source.get())?;
+        let date = date::field(&source, &self.field).and_then(Date::parse);
+        Ok(date.map(|date| self.template.replace("{date}", &self.format.format(&date))))
+    }
+}
+
+/// Where and how the docs are indexed.
+struct Target<'a> {
+    dest:      &'a Connection,
+    /// Index of the actions, the index each doc comes from if none.
+    index:     Option<&'a str>,
+    /// Index of the actions by the date of each doc.
+    dated:     Option<&'a DatedIndex>,
+    /// Action of each doc, `create` keeps docs which exist already.
+    op_type:   &'a str,
+    /// Whether the actions have the mapping type of each doc, as the destination is older than 7.
+    typed:     bool,
+    /// Query string parameters of each bulk request, like `refresh` and `pipeline`.
+    params:    Vec<(String, String)>,
+    bulk_size: usize,
+    limit:     Option<&'a RequestLimit>,
+}
+
+/// Indexes the docs of the pages into the destination in bulks of `bulk_size` docs.
+fn push(target: &Target, pages: &Receiver<Page<Source>>) -> Result<Pushed> {
+    let mut pushed = Pushed::default();
+    let mut body = String::new();
+    let mut count = 0;
+    for page in pages.iter() {
+        let docs = match page {
+            Page::Docs(_, docs) => docs,
+            Page::Done(_) => continue,
+        };
+        for doc in docs {
+            let Hit {
+                _index,
+                _id,
+                _type,
+                _routing,
+                _source,
+                ..
+            } = serde_json::from_str(doc.get())?;
+            let source = _source.ok_or_else(|| format!("document {} has no _source to index", _id))?;
+            let index = match target.dated {
+                Some(dated) => match dated.index(&source)? {
+                    Some(index) => index,
+                    None => {
+                        pushed.undated += 1;
+                        dated.fallback.clone()
+                    }
+                },
+                None => target.index.unwrap_or(&_index).to_string(),
+            };
+            let doc_type = match target.typed {
+                // docs of clusters from 8 have no type, `_doc` is the one type of 6 indices made for 7
+                true => Some(_type.as_deref().unwrap_or("_doc")),
+                false => None,
+            };
+            let action = action(target.op_type, &index, &_id, doc_type, _routing.as_deref());
+            body.push_str(&action.to_string());
+            body.push('\n');
+            body.push_str(source.get());
+            body.push('\n');
+            count += 1;
+            if count >= target.bulk_size {
+                send(target, &mut body, &mut count, &mut pushed)?;
+            }
+        }
+    }
+    if count > 0 {
+        send(target, &mut body, &mut count, &mut pushed)?;
+    }
+    Ok(pushed)
+}
+
+/// Action line of a doc, with a mapping type for clusters before 7 and the routing of the doc if it has one.
+fn action(op_type: &str, index: &str, id: &str, doc_type: Option<&str>, routing: Option<&str>) -> Value {
+    let mut action = json!({ "_index": index, "_id": id });
+    if let Some(doc_type) = doc_type {
+        action["_type"] = json!(doc_type);
+    }
+    if let Some(routing) = routing {
+        action["routing"] = json!(routing);
+    }
+    json!({ op_type: action })
+}
+
+/// Sends the buffered docs in a bulk request and counts them.
+fn send(target: &Target, body: &mut String, count: &mut usize, pushed: &mut Pushed) -> Result<()> {
+    let _permit = target.limit.map(RequestLimit::acquire);
+    let dest = target.dest;
+    let res = bulk(
+        &dest.client,
+        &dest.host,
+        mem::take(body),
+        &dest.user,
+        &dest.pass,
+        &target.params,
+    )?;
+    pushed.indexed += *count as u64 - res.failed - res.conflicts;
+    pushed.existing += res.conflicts;
+    pushed.failed += res.failed;
+    if pushed.first_failure.is_none() {
+        pushed.first_failure = res.first_failure;
+    }
+    *count = 0;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn action_has_the_type_only_if_given() {
+        assert_eq!(
+            action("index", "logs", "1", Some("_doc"), None),
+            json!({ "index": { "_index": "logs", "_id": "1", "_type": "_doc" } })
+        );
+        assert_eq!(
+            action("create", "logs", "1", None, None),
+            json!({ "create": { "_index": "logs", "_id": "1" } })
+        );
+    }
+
+    #[test]
+    fn action_keeps_the_routing() {
+        assert_eq!(
+            action("index", "logs", "1", None, Some("user-7")),
+            json!({ "index": { "_index": "logs", "_id": "1", "routing": "user-7" } })
+        );
+        assert_eq!(
+            action("index", "logs", "1", Some("_doc"), Some("user-7")),
+            json!({ "index": { "_index": "logs", "_id": "1", "_type": "_doc", "routing": "user-7" } })
+        );
+    }
+}
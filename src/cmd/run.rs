@@ -0,0 +1,80 @@
+use crate::cli::{PullOpt, RunOpt, StructOpt};
+use crate::cmd::pull::pull;
+use estunnel::common::Result;
+use std::env;
+use std::fs;
+use std::path::Path;
+use toml::Value;
+
+pub fn run(opt: RunOpt) -> Result<()> {
+    let RunOpt { job } = opt;
+    let text = fs::read_to_string(&job)?;
+    let table: toml::value::Table = toml::from_str(&text).map_err(|e| format!("{}: {}", job.display(), e))?;
+    let dir = job.parent().unwrap_or_else(|| Path::new(""));
+
+    let mut user = None;
+    let mut password_env = None;
+    let mut args = vec!["pull".to_string()];
+    for (key, value) in table {
+        let key = key.replace('_', "-");
+        match (key.as_str(), value) {
+            ("user", Value::String(s)) => user = Some(s),
+            ("password-env", Value::String(s)) => password_env = Some(s),
+            ("query", Value::Table(query)) => {
+                args.push("--query".into());
+                args.push(serde_json::to_string(&query)?);
+            }
+            (key @ ("query" | "coerce" | "header-file" | "watermark-file" | "index-file"), Value::String(path))
+                if !path.trim_start().starts_with('{') =>
+            {
+                args.push(format!("--{}", key));
+                args.push(dir.join(path).to_string_lossy().into_owned());
+            }
+            (key, Value::Array(values)) => {
+                for value in values {
+                    args.push(format!("--{}", key));
+                    args.push(arg(key, value)?);
+                }
+            }
+            (key, Value::Boolean(flag)) => {
+                if flag {
+                    args.push(format!("--{}", key));
+                }
+            }
+            (key, value) => {
+                args.push(format!("--{}", key));
+                args.push(arg(key, value)?);
+            }
+        }
+    }
+
+    // secrets are kept out of the job file, which is often checked in or readable by others
+    let user = match (user, password_env) {
+        (Some(user), Some(var)) => {
+            let pass =
+                env::var(&var).map_err(|_| format!("environment variable {} of password_env is not set", var))?;
+            Some(format!("{}:{}", user, pass))
+        }
+        (None, Some(_)) => return Err("password_env needs user to be set as well".into()),
+        (user, None) => user,
+    };
+    if let Some(user) = user {
+        args.push("--user".into());
+        args.push(user);
+    }
+
+    let opt = PullOpt::from_iter_safe(args)
+        .map_err(|e| format!("{}: {}", job.display(), e.message.lines().next().unwrap_or_default()))?;
+    pull(opt)
+}
+
+/// Turns a value of the job file into the argument of an option.
+fn arg(key: &str, value: Value) -> Result<String> {
+    match value {
+        Value::String(s) => Ok(s),
+        Value::Integer(n) => Ok(n.to_string()),
+        Value::Float(f) => Ok(f.to_string()),
+        Value::Datetime(d) => Ok(d.to_string()),
+        _ => Err(format!("unsupported value of `{}` in the job file", key).into()),
+    }
+}
@@ -1,4 +1,4 @@
-use crate::common::Result;
+use estunnel::common::Result;
 use regex::Regex;
 
 pub fn update() -> Result<()> {
@@ -14,7 +14,7 @@ pub fn update() -> Result<()> {
     let status = self_update::backends::github::Update::configure()
         .repo_owner(repo_owner)
         .repo_name(repo_name)
-        .target(&target)
+        .target(target)
         .bin_name(env!("CARGO_PKG_NAME"))
         .show_download_progress(true)
         .current_version(self_update::cargo_crate_version!())
@@ -0,0 +1,43 @@
+use crate::cli::ValidateOpt;
+use crate::cmd::{connect, load_query, Connection};
+use estunnel::common::Result;
+use estunnel::elastic::validate_query;
+use serde_json::{json, Value};
+
+pub fn validate(opt: ValidateOpt) -> Result<()> {
+    let ValidateOpt {
+        conn,
+        index,
+        query,
+        all_shards,
+    } = opt;
+    let Connection {
+        client,
+        host,
+        user,
+        pass,
+        ..
+    } = connect(conn)?;
+    let body = load_query(query.as_deref())?;
+    // the endpoint rejects the other parts of a search body
+    let query = json!({ "query": body.get("query").cloned().unwrap_or_else(|| json!({ "match_all": {} })) });
+    let res = validate_query(&client, &host, &index, &query, all_shards, &user, &pass)?;
+
+    let valid = res["valid"].as_bool().unwrap_or(false);
+    println!("Valid: {}", if valid { "yes" } else { "no" });
+    for explanation in res["explanations"].as_array().into_iter().flatten() {
+        let mut name = explanation["index"].as_str().unwrap_or("-").to_string();
+        if let Some(shard) = explanation["shard"].as_i64().filter(|&shard| shard >= 0) {
+            name = format!("{}[{}]", name, shard);
+        }
+        match (&explanation["explanation"], &explanation["error"]) {
+            (Value::String(explanation), _) => println!("{}: {}", name, explanation),
+            (_, Value::String(error)) => println!("{}: {}", name, error),
+            _ => println!("{}: {}", name, explanation),
+        }
+    }
+    if !valid {
+        return Err("the query is invalid".into());
+    }
+    Ok(())
+}
@@ -0,0 +1,151 @@
+use estunnel::common::Result;
+use serde_json::{json, Map, Value};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy)]
+enum Type {
+    String,
+    Int,
+    Float,
+    Bool,
+}
+
+impl Type {
+    fn name(self) -> &'static str {
+        match self {
+            Type::String => "string",
+            Type::Int => "int",
+            Type::Float => "float",
+            Type::Bool => "bool",
+        }
+    }
+}
+
+impl FromStr for Type {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "string" | "keyword" | "text" => Ok(Type::String),
+            "int" | "integer" | "long" => Ok(Type::Int),
+            "float" | "double" => Ok(Type::Float),
+            "bool" | "boolean" => Ok(Type::Bool),
+            s => Err(format!("expect one of string, int, float and bool, got `{}`", s)),
+        }
+    }
+}
+
+/// Types declared for fields of the docs, which are converted to them before writing.
+///
+/// Declarations are read from a json object of dotted field paths and type names, like `{"user.age": "int"}`.
+pub struct Coercion {
+    fields: Vec<(Vec<String>, Type)>,
+}
+
+impl Coercion {
+    pub fn load(path: &Path) -> Result<Coercion> {
+        let decls: Map<String, Value> = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+        let mut fields = Vec::with_capacity(decls.len());
+        for (field, ty) in decls {
+            let ty = ty
+                .as_str()
+                .ok_or_else(|| format!("type of field `{}` must be a string", field))?
+                .parse()
+                .map_err(|e| format!("type of field `{}`: {}", field, e))?;
+            fields.push((field.split('.').map(String::from).collect(), ty));
+        }
+        Ok(Coercion { fields })
+    }
+
+    /// Converts the declared fields of the doc, fields missing from it are left out.
+    pub fn apply(&self, doc: &mut Value) -> std::result::Result<(), String> {
+        for (path, ty) in &self.fields {
+            let value = path.iter().try_fold(&mut *doc, |value, key| value.get_mut(key));
+            if let Some(value) = value {
+                coerce(value, *ty)
+                    .map_err(|v| format!("cannot coerce `{}` of field `{}` to {}", v, path.join("."), ty.name()))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Converts the value, or each of the values of an array, returning the one which cannot be converted on failure.
+fn coerce(value: &mut Value, ty: Type) -> std::result::Result<(), Value> {
+    let coerced = match (ty, &*value) {
+        (_, Value::Null) => return Ok(()),
+        (_, Value::Array(_)) => {
+            for value in value.as_array_mut().unwrap() {
+                coerce(value, ty)?;
+            }
+            return Ok(());
+        }
+        (Type::String, Value::String(_)) | (Type::Float, Value::Number(_)) | (Type::Bool, Value::Bool(_)) => {
+            return Ok(())
+        }
+        (Type::String, Value::Number(n)) => Some(json!(n.to_string())),
+        (Type::String, Value::Bool(b)) => Some(json!(b.to_string())),
+        (Type::Int, Value::Number(n)) if n.is_i64() || n.is_u64() => return Ok(()),
+        (Type::Int, Value::Number(n)) => n.as_f64().filter(|f| f.fract() == 0.0).map(|f| json!(f as i64)),
+        (Type::Int, Value::String(s)) => s.trim().parse::<i64>().ok().map(|n| json!(n)),
+        (Type::Float, Value::String(s)) => s.trim().parse::<f64>().ok().filter(|f| f.is_finite()).map(|f| json!(f)),
+        (Type::Bool, Value::String(s)) => s.trim().parse::<bool>().ok().map(|b| json!(b)),
+        _ => None,
+    };
+    match coerced {
+        Some(coerced) => {
+            *value = coerced;
+            Ok(())
+        }
+        None => Err(value.clone()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn coerced(value: Value, ty: Type) -> std::result::Result<Value, Value> {
+        let mut value = value;
+        coerce(&mut value, ty).map(|_| value)
+    }
+
+    #[test]
+    fn floats_become_ints_only_without_a_fraction() {
+        assert_eq!(coerced(json!(3.0), Type::Int), Ok(json!(3)));
+        assert_eq!(coerced(json!(3.5), Type::Int), Err(json!(3.5)));
+        // a json number in exponent notation is a float
+        assert_eq!(
+            coerced(serde_json::from_str("1e3").unwrap(), Type::Int),
+            Ok(json!(1000))
+        );
+        assert_eq!(coerced(json!(" 42 "), Type::Int), Ok(json!(42)));
+        assert_eq!(coerced(json!("1e3"), Type::Int), Err(json!("1e3")));
+        assert_eq!(coerced(json!("1e3"), Type::Float), Ok(json!(1000.0)));
+    }
+
+    #[test]
+    fn arrays_are_coerced_by_item() {
+        assert_eq!(coerced(json!(["1", 2, null]), Type::Int), Ok(json!([1, 2, null])));
+        assert_eq!(coerced(json!([1, true]), Type::String), Ok(json!(["1", "true"])));
+        assert_eq!(coerced(json!(["1", "x"]), Type::Int), Err(json!("x")));
+    }
+
+    #[test]
+    fn error_names_the_value_field_and_type() {
+        let coercion = Coercion {
+            fields: vec![(vec!["user".into(), "age".into()], Type::Int)],
+        };
+        let mut doc = json!({ "user": { "age": "ten" } });
+        assert_eq!(
+            coercion.apply(&mut doc).unwrap_err(),
+            "cannot coerce `\"ten\"` of field `user.age` to int"
+        );
+        let mut doc = json!({ "user": { "name": "x" } });
+        assert!(coercion.apply(&mut doc).is_ok());
+        assert_eq!(doc, json!({ "user": { "name": "x" } }));
+    }
+}
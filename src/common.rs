@@ -1 +1,41 @@
+use std::sync::{Condvar, Mutex};
+
 pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+/// Limits how many requests to the cluster are in flight at the same time, shared by all that send them.
+pub struct RequestLimit {
+    max:      usize,
+    inflight: Mutex<usize>,
+    freed:    Condvar,
+}
+
+/// Allows sending a request until it is dropped.
+pub struct Permit<'a>(&'a RequestLimit);
+
+impl RequestLimit {
+    /// Creates a limit of `max` requests in flight, but at least one.
+    pub fn new(max: usize) -> RequestLimit {
+        RequestLimit {
+            max:      max.max(1),
+            inflight: Mutex::new(0),
+            freed:    Condvar::new(),
+        }
+    }
+
+    /// Blocks until another request may be sent.
+    pub fn acquire(&self) -> Permit<'_> {
+        let mut inflight = self.inflight.lock().unwrap();
+        while *inflight >= self.max {
+            inflight = self.freed.wait(inflight).unwrap();
+        }
+        *inflight += 1;
+        Permit(self)
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        *self.0.inflight.lock().unwrap() -= 1;
+        self.0.freed.notify_one();
+    }
+}
@@ -0,0 +1,239 @@
+use crate::output::civil_from_days;
+use serde_json::Value;
+use std::str::FromStr;
+
+/// Date pattern like `%Y.%m.%d`, with the `%Y`, `%m`, `%d` and `%H` of the date in UTC and `%%` for a `%`.
+#[derive(Debug, Clone)]
+pub struct DateFormat(String);
+
+impl FromStr for DateFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut directives = 0;
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                continue;
+            }
+            match chars.next() {
+                Some('Y' | 'm' | 'd' | 'H') => directives += 1,
+                Some('%') => {}
+                Some(c) => return Err(format!("unknown directive %{}, expect one of %Y, %m, %d, %H and %%", c)),
+                None => return Err(format!("unfinished directive at the end of `{}`", s)),
+            }
+        }
+        // without one all docs would get the same name
+        if directives == 0 {
+            return Err(format!("the format needs a date directive like %Y-%m-%d, got `{}`", s));
+        }
+        Ok(DateFormat(s.to_string()))
+    }
+}
+
+impl DateFormat {
+    pub fn format(&self, date: &Date) -> String {
+        format(&self.0, date)
+    }
+}
+
+/// Formats the directives of the pattern with the date.
+pub fn format(pattern: &str, date: &Date) -> String {
+    tokens(pattern)
+        .into_iter()
+        .map(|token| match token {
+            Token::Literal(c) => c.to_string(),
+            Token::Directive('Y') => format!("{:04}", date.year),
+            Token::Directive('m') => format!("{:02}", date.month),
+            Token::Directive('d') => format!("{:02}", date.day),
+            Token::Directive(_) => format!("{:02}", date.hour),
+        })
+        .collect()
+}
+
+/// Splits the pattern into literal chars and the chars of its directives.
+pub fn tokens(pattern: &str) -> Vec<Token> {
+    let mut tokens = vec![];
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '%' => match chars.next() {
+                Some('%') | None => tokens.push(Token::Literal('%')),
+                Some(c) => tokens.push(Token::Directive(c)),
+            },
+            c => tokens.push(Token::Literal(c)),
+        }
+    }
+    tokens
+}
+
+pub enum Token {
+    Literal(char),
+    Directive(char),
+}
+
+/// Value of the field of the doc, a dotted name like `event.created` is looked up in the nested objects too.
+pub fn field<'a>(doc: &'a Value, field: &str) -> Option<&'a Value> {
+    match doc.get(field) {
+        Some(value) => Some(value),
+        None => field.split('.').try_fold(doc, |value, key| value.get(key)),
+    }
+}
+
+/// Date and hour in UTC.
+pub struct Date {
+    year:  i64,
+    month: u32,
+    day:   u32,
+    hour:  u32,
+}
+
+impl Date {
+    fn from_millis(millis: i64) -> Date {
+        let secs = millis.div_euclid(1000);
+        let (year, month, day) = civil_from_days(secs.div_euclid(86400));
+        let hour = (secs.rem_euclid(86400) / 3600) as u32;
+        Date { year, month, day, hour }
+    }
+
+    /// Parses the value like `millis` does.
+    pub fn parse(value: &Value) -> Option<Date> {
+        millis(value).map(Date::from_millis)
+    }
+}
+
+/// Parses epoch milliseconds, the default of a `date` field, or an ISO 8601 date like `2023-01-31`,
+/// `2023-01-31T08:00:00Z` or `2023-01-31 16:00:00.123+08:00`, into epoch milliseconds.
+///
+/// A string of digits only is epoch milliseconds too, as elasticsearch takes it with the default
+/// `strict_date_optional_time||epoch_millis` format, so a basic date like `20230131` is a time in 1970.
+pub fn millis(value: &Value) -> Option<i64> {
+    match value {
+        Value::Number(n) => n.as_i64().or_else(|| n.as_f64().map(|f| f as i64)),
+        Value::String(s) if !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()) => s.parse().ok(),
+        Value::String(s) => parse_iso(s),
+        // the fields API and doc values return arrays
+        Value::Array(values) => values.first().and_then(millis),
+        _ => None,
+    }
+}
+
+fn parse_iso(s: &str) -> Option<i64> {
+    let num = |s: &str| s.parse::<i64>().ok();
+    let year = num(s.get(0..4)?)?;
+    let month = num(s.get(5..7)?)?;
+    let day = num(s.get(8..10)?)?;
+    if s.get(4..5)? != "-" || s.get(7..8)? != "-" || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let rest = &s[10..];
+    let (hour, minute, second, fraction, zone) = match rest.chars().next() {
+        None => (0, 0, 0, 0, ""),
+        Some('T' | ' ') => {
+            let hour = num(rest.get(1..3)?)?;
+            let minute = num(rest.get(4..6)?)?;
+            let (second, rest) = match rest[6..].strip_prefix(':') {
+                Some(rest) => (num(rest.get(0..2)?)?, &rest[2..]),
+                None => (0, &rest[6..]),
+            };
+            // fractions are cut to milliseconds
+            let (fraction, zone) = match rest.strip_prefix(&['.', ','][..]) {
+                Some(rest) => {
+                    let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+                    if digits == 0 {
+                        return None;
+                    }
+                    let millis = num(&format!("{:0<3}", &rest[..digits.min(3)]))?;
+                    (millis, &rest[digits..])
+                }
+                None => (0, rest),
+            };
+            (hour, minute, second, fraction, zone)
+        }
+        Some(_) => return None,
+    };
+    let offset = match zone {
+        "" | "Z" | "z" => 0,
+        zone => {
+            let sign = match zone.as_bytes()[0] {
+                b'+' => 1,
+                b'-' => -1,
+                _ => return None,
+            };
+            let digits = zone[1..].replace(':', "");
+            let hours = num(digits.get(0..2)?)?;
+            let minutes = match digits.get(2..) {
+                Some("") => 0,
+                Some(minutes) => num(minutes)?,
+                None => return None,
+            };
+            sign * (hours * 60 + minutes)
+        }
+    };
+    let minutes = days_from_civil(year, month, day) * 1440 + hour * 60 + minute - offset;
+    Some((minutes * 60 + second) * 1000 + fraction)
+}
+
+/// Days since 1970-01-01 of the date, see http://howardhinnant.github.io/date_algorithms.html
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let doy = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn days_count_from_the_epoch() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(1969, 12, 31), -1);
+        assert_eq!(days_from_civil(2000, 3, 1), 11_017);
+        assert_eq!(days_from_civil(2024, 2, 29), 19_782);
+        assert_eq!(days_from_civil(2023, 1, 31), 19_388);
+    }
+
+    #[test]
+    fn iso_dates_are_shifted_by_their_offset() {
+        let millis = |s: &str| millis(&json!(s));
+        let day = 19_388 * 86_400_000;
+        assert_eq!(millis("2023-01-31"), Some(day));
+        assert_eq!(millis("2023-01-31T08:00:00Z"), Some(day + 8 * 3_600_000));
+        assert_eq!(millis("2023-01-31 16:00:00+08:00"), Some(day + 8 * 3_600_000));
+        assert_eq!(millis("2023-01-31T05:30:00-0230"), Some(day + 8 * 3_600_000));
+        assert_eq!(millis("2023-01-31T08:00:01.5Z"), Some(day + 8 * 3_600_000 + 1_500));
+        assert_eq!(millis("2023-01-31T08:00+01"), Some(day + 7 * 3_600_000));
+        let date = Date::parse(&json!("2023-02-01T01:00:00+02:00")).unwrap();
+        assert_eq!((date.year, date.month, date.day, date.hour), (2023, 1, 31, 23));
+    }
+
+    #[test]
+    fn invalid_iso_dates_are_rejected() {
+        for s in [
+            "2023-13-01",
+            "2023-01-32",
+            "2023/01/31",
+            "2023-01-31X",
+            "2023-01-31T08:00:00.Z",
+            "2023-01-31T08:00~1",
+        ] {
+            assert_eq!(millis(&json!(s)), None, "{}", s);
+        }
+    }
+
+    #[test]
+    fn digits_are_epoch_millis() {
+        assert_eq!(millis(&json!(1_675_152_000_000u64)), Some(1_675_152_000_000));
+        assert_eq!(millis(&json!("1675152000000")), Some(1_675_152_000_000));
+        assert_eq!(millis(&json!(1.5e3)), Some(1_500));
+        assert_eq!(millis(&json!([86_400_000, 0])), Some(86_400_000));
+        // like elasticsearch does
+        assert_eq!(millis(&json!("20230131")), Some(20_230_131));
+        assert_eq!(millis(&json!(true)), None);
+    }
+}
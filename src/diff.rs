@@ -0,0 +1,141 @@
+use crate::output::{self, Compression};
+use estunnel::common::Result;
+use serde_json::{json, Value};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// The docs of a previous export by `_id`, so that an export only writes the docs which are new or have changed since.
+///
+/// The previous export has to be of whole hits, which have the `_id` beside the `_source`.
+pub struct Diff {
+    /// Hash of the source of each previous doc, and whether it is pulled again.
+    previous:      Mutex<HashMap<String, (u64, bool)>>,
+    /// Count of docs skipped for being unchanged.
+    pub unchanged: AtomicU64,
+}
+
+impl Diff {
+    /// Loads the previous export from its files, which may be compressed as their extension tells.
+    pub fn load(paths: &[PathBuf]) -> Result<Diff> {
+        let mut previous = HashMap::new();
+        for path in paths {
+            let reader =
+                output::reader(path, Compression::of_path(path)).map_err(|e| format!("{}: {}", path.display(), e))?;
+            for (i, line) in BufReader::new(reader).lines().enumerate() {
+                let hit: Value =
+                    serde_json::from_str(&line?).map_err(|e| format!("{}:{}: {}", path.display(), i + 1, e))?;
+                let id = match hit["_id"].as_str() {
+                    Some(id) => id.to_string(),
+                    None => {
+                        return Err(format!(
+                        "{}:{}: no _id, the previous export has to be of whole hits like one made with --with-seq-no",
+                        path.display(),
+                        i + 1
+                    )
+                        .into())
+                    }
+                };
+                previous.insert(id, (hash(&hit["_source"]), false));
+            }
+        }
+        Ok(Diff {
+            previous:  Mutex::new(previous),
+            unchanged: AtomicU64::new(0),
+        })
+    }
+
+    /// Returns whether the hit is new or its source differs from the previous export.
+    pub fn changed(&self, hit: &Value) -> bool {
+        let id = match hit["_id"].as_str() {
+            Some(id) => id,
+            None => return true,
+        };
+        match self.previous.lock().unwrap().get_mut(id) {
+            Some((source, seen)) => {
+                *seen = true;
+                if *source != hash(&hit["_source"]) {
+                    return true;
+                }
+            }
+            None => return true,
+        }
+        self.unchanged.fetch_add(1, Ordering::Relaxed);
+        false
+    }
+
+    /// Writes the ids of the previous docs which were not pulled again as json lines, and returns their count.
+    pub fn save_deleted(&self, path: &Path) -> io::Result<u64> {
+        let mut file = BufWriter::new(File::create(path)?);
+        let previous = self.previous.lock().unwrap();
+        let mut deleted: Vec<_> = previous
+            .iter()
+            .filter(|(_, (_, seen))| !seen)
+            .map(|(id, _)| id)
+            .collect();
+        deleted.sort();
+        for id in &deleted {
+            writeln!(file, "{}", json!({ "_id": id }))?;
+        }
+        file.flush()?;
+        Ok(deleted.len() as u64)
+    }
+}
+
+/// Hashes the source regardless of the order of its keys, which the maps of serde_json keep sorted.
+fn hash(source: &Value) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    source.to_string().hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn only_changed_and_new_docs_are_written_and_the_rest_deleted() {
+        let dir = std::env::temp_dir().join(format!("estunnel-diff-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let previous = dir.join("previous.json");
+        fs::write(
+            &previous,
+            "{\"_id\":\"1\",\"_source\":{\"a\":1,\"b\":2}}\n\
+             {\"_id\":\"2\",\"_source\":{\"a\":2}}\n\
+             {\"_id\":\"3\",\"_source\":{\"a\":3}}\n",
+        )
+        .unwrap();
+        let diff = Diff::load(&[previous]).unwrap();
+
+        // the order of the keys does not matter
+        assert!(!diff.changed(&json!({ "_id": "1", "_source": { "b": 2, "a": 1 } })));
+        assert!(diff.changed(&json!({ "_id": "2", "_source": { "a": 20 } })));
+        assert!(diff.changed(&json!({ "_id": "4", "_source": { "a": 4 } })));
+        assert!(diff.changed(&json!({ "_source": { "a": 5 } })));
+        assert_eq!(diff.unchanged.load(Ordering::Relaxed), 1);
+
+        let deleted = dir.join("deleted.json");
+        assert_eq!(diff.save_deleted(&deleted).unwrap(), 1);
+        assert_eq!(fs::read_to_string(&deleted).unwrap(), "{\"_id\":\"3\"}\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn previous_docs_need_an_id() {
+        let path = std::env::temp_dir().join(format!("estunnel-diff-source-{}.json", std::process::id()));
+        fs::write(&path, "{\"a\":1}\n").unwrap();
+        let error = Diff::load(std::slice::from_ref(&path)).err().unwrap().to_string();
+        fs::remove_file(&path).unwrap();
+        assert!(
+            error.ends_with(":1: no _id, the previous export has to be of whole hits like one made with --with-seq-no"),
+            "{}",
+            error
+        );
+    }
+}
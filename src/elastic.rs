@@ -1,21 +1,65 @@
 use crate::common::Result;
-use reqwest::blocking::{Client, Response};
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE, WARNING};
+use reqwest::redirect::Policy;
+use reqwest::{Proxy, StatusCode, Url};
 use serde::de::{self, MapAccess};
 use serde_derive::*;
-use serde_json::{self, value::RawValue, Value};
-use std::collections::HashMap;
+use serde_json::value::{to_raw_value, RawValue};
+use serde_json::{self, json, Value};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
 use std::fmt;
 use std::result;
+use std::time::Duration;
 
 pub type Source = Box<RawValue>;
 
 #[derive(Serialize, Deserialize)]
 pub struct ScrollResponse {
+    /// Empty if the cluster left it out.
+    #[serde(default)]
     pub _scroll_id: String,
     pub took:       u32,
+    #[serde(default)]
+    pub _shards:    Shards,
     pub hits:       Hits,
 }
 
+#[derive(Serialize, Deserialize, Default)]
+pub struct Shards {
+    pub total:      u64,
+    pub successful: u64,
+    #[serde(default)]
+    pub skipped:    u64,
+    pub failed:     u64,
+    #[serde(default)]
+    pub failures:   Vec<ShardFailure>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ShardFailure {
+    pub index:  Option<String>,
+    pub shard:  Option<i64>,
+    pub reason: Value,
+}
+
+impl fmt::Display for ShardFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "[{}][{}] ",
+            self.index.as_deref().unwrap_or("_na_"),
+            self.shard.unwrap_or(-1)
+        )?;
+        match (self.reason["type"].as_str(), self.reason["reason"].as_str()) {
+            (Some(kind), Some(reason)) => write!(f, "{}: {}", kind, reason),
+            _ => write!(f, "{}", self.reason),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Hits {
     #[serde(deserialize_with = "parse_total")]
@@ -25,7 +69,72 @@ pub struct Hits {
 
 #[derive(Serialize, Deserialize)]
 pub struct Hit {
-    pub _source: Source,
+    #[serde(default)]
+    pub _index:        String,
+    #[serde(default)]
+    pub _id:           String,
+    /// Mapping type of clusters before 8, which deprecate it from 7 and no longer return it from 8.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub _type:         Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub _routing:      Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub _seq_no:       Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub _primary_term: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub _source:       Option<Source>,
+    /// Values retrieved by the `fields` option of the search, formatted according to the mapping.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fields:        Option<Source>,
+    /// Snippets of the matching fields requested by the `highlight` option of the search.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub highlight:     Option<Source>,
+    /// Matching nested or child docs requested by the `inner_hits` option of `nested` or `has_child` queries.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inner_hits:    Option<Source>,
+}
+
+/// Search response whose hits borrow from the body, so only the parts making the docs are copied out of it.
+#[derive(Deserialize)]
+struct RawResponse<H> {
+    #[serde(default)]
+    _scroll_id: String,
+    took:       u32,
+    #[serde(default)]
+    _shards:    Shards,
+    hits:       RawHits<H>,
+}
+
+#[derive(Deserialize)]
+struct RawHits<H> {
+    #[serde(deserialize_with = "parse_total")]
+    total: u64,
+    hits:  Vec<H>,
+}
+
+/// The parts of a hit which can make a doc, borrowed from the body.
+#[derive(Deserialize)]
+struct RawHit<'a> {
+    #[serde(default, borrow)]
+    _id:        Cow<'a, str>,
+    #[serde(borrow)]
+    _source:    Option<&'a RawValue>,
+    #[serde(borrow)]
+    fields:     Option<&'a RawValue>,
+    #[serde(borrow)]
+    highlight:  Option<&'a RawValue>,
+    #[serde(borrow)]
+    inner_hits: Option<&'a RawValue>,
+}
+
+/// Part of a hit which makes a doc of the output.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DocKind {
+    Source,
+    Fields,
+    /// The whole hit including its metadata.
+    Hit,
 }
 
 fn parse_total<'de, D>(deserializer: D) -> result::Result<u64, D::Error>
@@ -65,29 +174,701 @@ where
     deserializer.deserialize_any(TotalVisitor)
 }
 
-pub fn parse_response(res: Response) -> Result<(Vec<Box<RawValue>>, String, u64)> {
+/// Parts of a hit besides the doc which are merged into `_source` or `fields` docs, see [`parse_response`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Extras {
+    pub highlight:  bool,
+    pub inner_hits: bool,
+}
+
+impl Extras {
+    /// Adds the wanted parts of the hit to its doc as `_highlight` and `_inner_hits`, docs of hits without them are
+    /// left alone.
+    fn merge(self, doc: Source, highlight: Option<Source>, inner_hits: Option<Source>) -> Result<Source> {
+        let mut extras = vec![];
+        if self.highlight {
+            extras.extend(highlight.map(|value| ("_highlight", value)));
+        }
+        if self.inner_hits {
+            extras.extend(inner_hits.map(|value| ("_inner_hits", value)));
+        }
+        if extras.is_empty() {
+            return Ok(doc);
+        }
+        // spliced into the raw doc to keep its fields as they are
+        let mut fields = doc
+            .get()
+            .trim_start()
+            .strip_prefix('{')
+            .ok_or("only object docs can have parts of the hit merged")?
+            .to_string();
+        for (name, value) in extras.iter().rev() {
+            let sep = if fields.trim_start().starts_with('}') { "" } else { "," };
+            fields = format!("\"{}\":{}{}{}", name, value.get(), sep, fields);
+        }
+        Ok(RawValue::from_string(format!("{{{}", fields))?)
+    }
+}
+
+/// Docs of a search response along with the bookkeeping info.
+pub struct SearchPage {
+    pub docs:      Vec<Source>,
+    pub scroll_id: String,
+    pub total:     u64,
+    pub shards:    Shards,
+    /// Milliseconds the cluster spent on the search.
+    pub took:      u32,
+    /// Whether the response is not valid UTF-8, whose invalid sequences are replaced with U+FFFD.
+    pub lossy:     bool,
+}
+
+/// Parses a search response into a page whose docs are the `kind` part of each hit.
+///
+/// With `extras` the `highlight` and the `inner_hits` of each hit are merged into its `_source` or `fields` doc as
+/// `_highlight` and `_inner_hits`, whole hits have them as they are anyway.
+pub fn parse_response(res: Response, kind: DocKind, extras: Extras) -> Result<SearchPage> {
     // read body into a string firstly to improve performance.
     // See: https://github.com/serde-rs/json/issues/160
-    let res: ScrollResponse = serde_json::from_str(&res.text()?)?;
-    let docs = res.hits.hits.into_iter().map(|hit| hit._source).collect();
-    Ok((docs, res._scroll_id, res.hits.total))
+    let body = res.bytes()?;
+    let (body, lossy) = match std::str::from_utf8(&body) {
+        Ok(body) => (Cow::Borrowed(body), false),
+        Err(_) => (String::from_utf8_lossy(&body), true),
+    };
+    match kind {
+        // copied out of the body as they are, without parsing them into parts
+        DocKind::Hit => into_page(serde_json::from_str::<RawResponse<&RawValue>>(&body)?, lossy, |hit| {
+            Ok(hit.to_owned())
+        }),
+        _ => into_page(serde_json::from_str::<RawResponse<RawHit>>(&body)?, lossy, |hit| {
+            let doc = match kind {
+                DocKind::Source => hit
+                    ._source
+                    .ok_or_else(|| format!("hit {} has no _source", hit._id))?
+                    .to_owned(),
+                // hits without any of the requested fields have none of them
+                _ => hit
+                    .fields
+                    .map_or_else(|| to_raw_value(&json!({})), |fields| Ok(fields.to_owned()))?,
+            };
+            extras.merge(
+                doc,
+                hit.highlight.map(ToOwned::to_owned),
+                hit.inner_hits.map(ToOwned::to_owned),
+            )
+        }),
+    }
+}
+
+fn into_page<H>(res: RawResponse<H>, lossy: bool, doc: impl FnMut(H) -> Result<Source>) -> Result<SearchPage> {
+    Ok(SearchPage {
+        docs: res.hits.hits.into_iter().map(doc).collect::<Result<_>>()?,
+        scroll_id: res._scroll_id,
+        total: res.hits.total,
+        shards: res._shards,
+        took: res.took,
+        lossy,
+    })
+}
+
+/// Connection pool settings of the http client, the defaults of reqwest are used for those not set.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PoolOptions {
+    pub max_idle_per_host: Option<usize>,
+    pub idle_timeout:      Option<Duration>,
+}
+
+/// Builds the http client used to talk to the cluster, sending `headers` with every request.
+///
+/// All requests go through `proxy` if given, instead of the proxies set by environment variables.
+pub fn build_client(
+    no_proxy: bool,
+    proxy: Option<&str>,
+    headers: &[(String, String)],
+    pool: PoolOptions,
+) -> Result<Client> {
+    let mut map = HeaderMap::new();
+    for (name, value) in headers {
+        map.append(HeaderName::from_bytes(name.as_bytes())?, HeaderValue::from_str(value)?);
+    }
+    let mut builder = Client::builder().default_headers(map).redirect(redirect_policy());
+    if no_proxy {
+        builder = builder.no_proxy();
+    }
+    if let Some(proxy) = proxy {
+        builder = builder.proxy(Proxy::all(proxy)?);
+    }
+    if let Some(max) = pool.max_idle_per_host {
+        builder = builder.pool_max_idle_per_host(max);
+    }
+    if let Some(timeout) = pool.idle_timeout {
+        builder = builder.pool_idle_timeout(timeout);
+    }
+    Ok(builder.build()?)
+}
+
+/// Follows redirects only if they keep the request as it is, as the search requests are POSTs with a body.
+///
+/// Redirects with status 301, 302 and 303 turn the request into a GET without body, and those to another origin drop
+/// the credentials, both of which would give confusing results instead of an error.
+fn redirect_policy() -> Policy {
+    Policy::custom(|attempt| {
+        let to = attempt.url();
+        let from = attempt.previous().last().unwrap_or(to);
+        let origin = |url: &Url| url.origin().ascii_serialization();
+        if origin(to) != origin(from) {
+            let msg = format!("the cluster redirects to {}, use --host {} instead", to, origin(to));
+            attempt.error(msg)
+        } else if attempt.status() != StatusCode::TEMPORARY_REDIRECT
+            && attempt.status() != StatusCode::PERMANENT_REDIRECT
+        {
+            let msg = format!(
+                "the cluster redirects to {} with status {}, which drops the request body",
+                to,
+                attempt.status()
+            );
+            attempt.error(msg)
+        } else if attempt.previous().len() > 10 {
+            attempt.error("too many redirects")
+        } else {
+            attempt.follow()
+        }
+    })
+}
+
+/// Url of the api `path` like `logs/_search` on the host, which may have a base path like `https://gw/es` when the
+/// cluster is behind a path-based reverse proxy.
+pub fn endpoint(host: &str, path: &str) -> String {
+    match Url::parse(host) {
+        Ok(mut base) => {
+            if !base.path().ends_with('/') {
+                let dir = format!("{}/", base.path());
+                base.set_path(&dir);
+            }
+            // `./` keeps an index of a remote cluster like `remote:logs` from being taken for a scheme
+            base.join(&format!("./{}", path.trim_start_matches('/'))).map_or_else(
+                |_| format!("{}/{}", host.trim_end_matches('/'), path),
+                |url| url.to_string(),
+            )
+        }
+        Err(_) => format!("{}/{}", host.trim_end_matches('/'), path),
+    }
 }
 
 pub fn request_elastic(
     client: &Client,
     url: &str,
     query: &Value,
-    user: &str,
+    user: &Option<String>,
     pass: &Option<String>,
     params: &Option<Vec<(&str, String)>>,
 ) -> Result<Response> {
-    let res = client.post(url).basic_auth(user, pass.clone()).json(query);
+    let res = client.post(url).authorize(user, pass).json(query);
 
     let res = match params {
         Some(params) => res.query(params),
         _ => res,
     };
 
-    let res = res.send()?.error_for_status()?;
+    check_status(res.send()?)
+}
+
+/// Texts of the `Warning` headers of a response, like deprecation notices.
+///
+/// Values in the format `299 Elasticsearch-7.10.0-... "text" "date"` are reduced to the text.
+pub fn warnings(res: &Response) -> Vec<String> {
+    res.headers()
+        .get_all(WARNING)
+        .iter()
+        .map(|value| {
+            let value = String::from_utf8_lossy(value.as_bytes());
+            let text = value.split_once('"').and_then(|(_, rest)| {
+                let end = rest
+                    .char_indices()
+                    .find(|&(i, c)| c == '"' && !rest[..i].ends_with('\\'))?
+                    .0;
+                Some(rest[..end].replace("\\\"", "\""))
+            });
+            text.unwrap_or_else(|| value.into_owned())
+        })
+        .collect()
+}
+
+pub fn get_elastic(client: &Client, url: &str, user: &Option<String>, pass: &Option<String>) -> Result<Response> {
+    check_status(client.get(url).authorize(user, pass).send()?)
+}
+
+/// Version number of the cluster like `7.10.2`.
+pub fn cluster_version(client: &Client, host: &str, user: &Option<String>, pass: &Option<String>) -> Result<String> {
+    let res: Value = get_elastic(client, &endpoint(host, ""), user, pass)?.json()?;
+    let version = res["version"]["number"]
+        .as_str()
+        .ok_or("the cluster reports no version number")?;
+    Ok(version.to_string())
+}
+
+/// Whether bulk actions sent to a cluster of the version like `6.8.23` must have a mapping type. Clusters before 7
+/// require one, 7 deprecates it and 8 rejects it.
+pub fn needs_mapping_type(version: &str) -> Result<bool> {
+    let major: u32 = version
+        .split('.')
+        .next()
+        .and_then(|major| major.parse().ok())
+        .ok_or_else(|| format!("invalid version number {}", version))?;
+    Ok(major < 7)
+}
+
+/// Count of the docs of the indices matching `index`, without nested docs unlike `_cat/indices`.
+pub fn doc_count(
+    client: &Client,
+    host: &str,
+    index: &str,
+    user: &Option<String>,
+    pass: &Option<String>,
+) -> Result<u64> {
+    let res: Value = get_elastic(client, &endpoint(host, &format!("{}/_count", index)), user, pass)?.json()?;
+    Ok(res["count"].as_u64().ok_or("the cluster reports no doc count")?)
+}
+
+/// Count of the docs of the indices matching `index` which match the `query` of the search body.
+pub fn query_count(
+    client: &Client,
+    host: &str,
+    index: &str,
+    body: &Value,
+    user: &Option<String>,
+    pass: &Option<String>,
+) -> Result<u64> {
+    let url = endpoint(host, &format!("{}/_count", index));
+    // `_count` only takes the query, not the other keys of a search body like `sort`
+    let query = match body.get("query") {
+        Some(query) => json!({ "query": query }),
+        None => json!({}),
+    };
+    let res: Value = request_elastic(client, &url, &query, user, pass, &None)?.json()?;
+    Ok(res["count"].as_u64().ok_or("the cluster reports no doc count")?)
+}
+
+/// Response of `_validate/query` explaining the query against the indices matching `index`, or every shard of them
+/// with `all_shards`.
+pub fn validate_query(
+    client: &Client,
+    host: &str,
+    index: &str,
+    query: &Value,
+    all_shards: bool,
+    user: &Option<String>,
+    pass: &Option<String>,
+) -> Result<Value> {
+    let url = endpoint(host, &format!("{}/_validate/query", index));
+    let mut params = vec![("explain", "true".to_string())];
+    if all_shards {
+        params.push(("all_shards", "true".to_string()));
+    }
+    Ok(request_elastic(client, &url, query, user, pass, &Some(params))?.json()?)
+}
+
+/// Flat settings like `index.max_result_window` of each index matching `index`, including the defaults.
+pub fn index_settings(
+    client: &Client,
+    host: &str,
+    index: &str,
+    user: &Option<String>,
+    pass: &Option<String>,
+) -> Result<BTreeMap<String, HashMap<String, Value>>> {
+    let url = endpoint(
+        host,
+        &format!("{}/_settings?flat_settings=true&include_defaults=true", index),
+    );
+    let res: BTreeMap<String, Value> = get_elastic(client, &url, user, pass)?.json()?;
+    Ok(res
+        .into_iter()
+        .map(|(index, res)| (index, merge_settings(&res, &["defaults", "settings"])))
+        .collect())
+}
+
+/// Flat setting of the cluster like `search.max_open_scroll_context`, none if the cluster does not know it.
+pub fn cluster_setting(
+    client: &Client,
+    host: &str,
+    name: &str,
+    user: &Option<String>,
+    pass: &Option<String>,
+) -> Result<Option<Value>> {
+    let url = endpoint(host, "_cluster/settings?flat_settings=true&include_defaults=true");
+    let res: Value = get_elastic(client, &url, user, pass)?.json()?;
+    Ok(merge_settings(&res, &["defaults", "persistent", "transient"]).remove(name))
+}
+
+/// Merges the groups of flat settings of a response, later groups take precedence.
+fn merge_settings(res: &Value, groups: &[&str]) -> HashMap<String, Value> {
+    groups
+        .iter()
+        .filter_map(|group| res[group].as_object())
+        .flatten()
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect()
+}
+
+/// An index as listed by `_cat/indices`, whose numbers are strings.
+#[derive(Debug, Deserialize)]
+pub struct IndexInfo {
+    pub index:      String,
+    #[serde(rename = "docs.count")]
+    pub docs_count: Option<String>,
+    #[serde(rename = "store.size")]
+    pub store_size: Option<String>,
+    /// Number of primary shards.
+    pub pri:        Option<String>,
+}
+
+/// Lists the indices matching the pattern.
+pub fn cat_indices(
+    client: &Client,
+    host: &str,
+    pattern: &str,
+    user: &Option<String>,
+    pass: &Option<String>,
+) -> Result<Vec<IndexInfo>> {
+    let url = endpoint(host, &format!("_cat/indices/{}?format=json", pattern));
+    Ok(get_elastic(client, &url, user, pass)?.json()?)
+}
+
+/// Types of the fields in the mappings of each index matching `index`, keyed by dotted field path, type and then
+/// listing the indices.
+pub type FieldTypes = BTreeMap<String, BTreeMap<String, Vec<String>>>;
+
+/// Fetches the mappings of the indices matching `index` and collects the types of their fields.
+pub fn field_types(
+    client: &Client,
+    host: &str,
+    index: &str,
+    user: &Option<String>,
+    pass: &Option<String>,
+) -> Result<FieldTypes> {
+    let url = endpoint(host, &format!("{}/_mapping", index));
+    let res: HashMap<String, Value> = get_elastic(client, &url, user, pass)?.json()?;
+    let mut types = FieldTypes::new();
+    for (index, mapping) in res {
+        let mappings = &mapping["mappings"];
+        // mappings of clusters before 7.0 are nested in their doc type
+        let roots: Vec<&Value> = match mappings.get("properties") {
+            Some(_) => vec![mappings],
+            None => mappings
+                .as_object()
+                .into_iter()
+                .flat_map(|types| types.values())
+                .collect(),
+        };
+        for root in roots {
+            collect_field_types(root, "", &index, &mut types);
+        }
+    }
+    for indices in types.values_mut().flat_map(|types| types.values_mut()) {
+        indices.sort();
+    }
+    Ok(types)
+}
+
+fn collect_field_types(mapping: &Value, prefix: &str, index: &str, types: &mut FieldTypes) {
+    let fields = mapping["properties"].as_object().into_iter().flatten();
+    // multi-fields like `name.keyword`
+    let multi_fields = mapping["fields"].as_object().into_iter().flatten();
+    for (name, field) in fields.chain(multi_fields) {
+        let path = match prefix {
+            "" => name.to_string(),
+            prefix => format!("{}.{}", prefix, name),
+        };
+        let ty = field["type"].as_str().unwrap_or(if field["properties"].is_object() {
+            "object"
+        } else {
+            "unknown"
+        });
+        types
+            .entry(path.clone())
+            .or_default()
+            .entry(ty.to_string())
+            .or_default()
+            .push(index.to_string());
+        collect_field_types(field, &path, index, types);
+    }
+}
+
+/// Returns the number of shards a search on the index covers, only those of the routing values if any.
+pub fn shard_count(
+    client: &Client,
+    host: &str,
+    index: &str,
+    routing: Option<&str>,
+    user: &Option<String>,
+    pass: &Option<String>,
+) -> Result<u64> {
+    #[derive(Deserialize)]
+    struct SearchShards {
+        shards: Vec<Value>,
+    }
+    let url = Url::parse_with_params(
+        &endpoint(host, &format!("{}/_search_shards", index)),
+        routing.map(|routing| ("routing", routing)),
+    )?;
+    let res: SearchShards = get_elastic(client, url.as_str(), user, pass)?.json()?;
+    Ok(res.shards.len() as u64)
+}
+
+pub fn clear_scroll(
+    client: &Client,
+    host: &str,
+    scroll_id: &str,
+    user: &Option<String>,
+    pass: &Option<String>,
+) -> Result<()> {
+    let url = endpoint(host, "_search/scroll");
+    let res = client
+        .delete(&url)
+        .authorize(user, pass)
+        .json(&json!({ "scroll_id": [scroll_id] }))
+        .send()?;
+    check_status(res)?;
+    Ok(())
+}
+
+/// Clears all open scroll contexts of the cluster, those of other clients as well, and returns how many were freed.
+pub fn clear_all_scrolls(client: &Client, host: &str, user: &Option<String>, pass: &Option<String>) -> Result<u64> {
+    let url = endpoint(host, "_search/scroll/_all");
+    let res = client.delete(&url).authorize(user, pass).send()?;
+    let res: Value = check_status(res)?.json()?;
+    Ok(res["num_freed"].as_u64().unwrap_or(0))
+}
+
+/// Outcome of a `_bulk` request, whose items may fail one by one.
+pub struct BulkResult {
+    /// Items which failed for another reason than a version conflict.
+    pub failed:        u64,
+    /// Items which failed on a version conflict, like a `create` of a doc which exists.
+    pub conflicts:     u64,
+    /// Reason of the first failed item.
+    pub first_failure: Option<String>,
+}
+
+/// Sends the ndjson action and source lines to `_bulk`.
+pub fn bulk(
+    client: &Client,
+    host: &str,
+    body: String,
+    user: &Option<String>,
+    pass: &Option<String>,
+    params: &[(String, String)],
+) -> Result<BulkResult> {
+    #[derive(Deserialize)]
+    struct BulkResponse {
+        errors: bool,
+        #[serde(default)]
+        items:  Vec<HashMap<String, Value>>,
+    }
+    let url = endpoint(host, "_bulk");
+    let res = client
+        .post(&url)
+        .authorize(user, pass)
+        .header(CONTENT_TYPE, "application/x-ndjson")
+        .query(params)
+        .body(body)
+        .send()?;
+    let res: BulkResponse = check_status(res)?.json()?;
+    let mut result = BulkResult {
+        failed:        0,
+        conflicts:     0,
+        first_failure: None,
+    };
+    if !res.errors {
+        return Ok(result);
+    }
+    for error in res
+        .items
+        .iter()
+        .flat_map(|item| item.values())
+        .map(|item| &item["error"])
+    {
+        if error.is_null() {
+            continue;
+        }
+        if error["type"] == "version_conflict_engine_exception" {
+            result.conflicts += 1;
+            continue;
+        }
+        result.failed += 1;
+        if result.first_failure.is_none() {
+            result.first_failure = Some(match (error["type"].as_str(), error["reason"].as_str()) {
+                (Some(kind), Some(reason)) => format!("{}: {}", kind, reason),
+                _ => error.to_string(),
+            });
+        }
+    }
+    Ok(result)
+}
+
+/// Basic authorization of the requests to the cluster.
+trait Authorize {
+    /// Authorizes the request as `user`, or leaves it anonymous without a user.
+    fn authorize(self, user: &Option<String>, pass: &Option<String>) -> Self;
+}
+
+impl Authorize for RequestBuilder {
+    fn authorize(self, user: &Option<String>, pass: &Option<String>) -> Self {
+        match user {
+            Some(user) => self.basic_auth(user, pass.clone()),
+            None => self,
+        }
+    }
+}
+
+/// Turns an error response into an error carrying the response body, which holds the reason reported by the cluster.
+fn check_status(res: Response) -> Result<Response> {
+    let status = res.status();
+    if status.is_client_error() || status.is_server_error() {
+        return Err(Box::new(HttpError {
+            status,
+            body: res.text()?,
+        }));
+    }
     Ok(res)
 }
+
+/// An error response of the cluster.
+pub struct HttpError {
+    pub status: StatusCode,
+    pub body:   String,
+}
+
+impl HttpError {
+    /// Formats the type, the reason, the causes and the root causes of a json error body on lines of their own, or
+    /// the body as it is if it is not one.
+    pub fn pretty(&self) -> String {
+        let body: Value = match serde_json::from_str(&self.body) {
+            Ok(body) => body,
+            Err(_) => return self.to_string(),
+        };
+        let error = &body["error"];
+        if !error.is_object() {
+            return self.to_string();
+        }
+        let describe = |e: &Value| match (e["type"].as_str(), e["reason"].as_str()) {
+            (Some(kind), Some(reason)) => format!("{}: {}", kind, reason),
+            _ => e.to_string(),
+        };
+        let mut msg = format!("HTTP status {}\n    {}", self.status, describe(error));
+        let mut cause = &error["caused_by"];
+        while cause.is_object() {
+            msg += &format!("\n    caused by: {}", describe(cause));
+            cause = &cause["caused_by"];
+        }
+        for root_cause in error["root_cause"].as_array().into_iter().flatten() {
+            msg += &format!("\n    root cause: {}", describe(root_cause));
+        }
+        msg
+    }
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "HTTP status {}: {}", self.status, self.body)
+    }
+}
+
+impl fmt::Debug for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self.to_string(), f)
+    }
+}
+
+impl Error for HttpError {}
+
+/// Formats the error, an error response of the cluster in the readable form of [`HttpError::pretty`] if `pretty`.
+pub fn describe_error(e: &(dyn Error + 'static), pretty: bool) -> String {
+    match e.downcast_ref::<HttpError>() {
+        Some(e) if pretty => e.pretty(),
+        _ => e.to_string(),
+    }
+}
+
+/// Returns whether the error is caused by a request timeout.
+pub fn is_timeout(e: &(dyn Error + 'static)) -> bool {
+    e.downcast_ref::<reqwest::Error>().is_some_and(|e| e.is_timeout())
+}
+
+/// Returns whether the request may succeed if sent again, as it timed out, could not be sent or the cluster is
+/// overloaded or unavailable for now.
+pub fn is_transient(e: &(dyn Error + 'static)) -> bool {
+    if let Some(e) = e.downcast_ref::<reqwest::Error>() {
+        return e.is_timeout() || e.is_request();
+    }
+    e.downcast_ref::<HttpError>().is_some_and(|e| {
+        matches!(
+            e.status,
+            StatusCode::TOO_MANY_REQUESTS
+                | StatusCode::BAD_GATEWAY
+                | StatusCode::SERVICE_UNAVAILABLE
+                | StatusCode::GATEWAY_TIMEOUT
+        )
+    })
+}
+
+/// Returns whether the error is the cluster answering that the index does not exist.
+pub fn is_index_not_found(e: &(dyn Error + 'static)) -> bool {
+    e.downcast_ref::<HttpError>()
+        .is_some_and(|e| e.status == StatusCode::NOT_FOUND)
+}
+
+/// Returns whether the error is caused by the cluster refusing to open another scroll context.
+pub fn is_too_many_scrolls(e: &(dyn Error + 'static)) -> bool {
+    e.to_string().contains("Trying to create too many scroll contexts")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mapping_type_is_needed_before_7() {
+        assert!(needs_mapping_type("5.6.16").unwrap());
+        assert!(needs_mapping_type("6.8.23").unwrap());
+        assert!(!needs_mapping_type("7.10.2").unwrap());
+        assert!(!needs_mapping_type("8.11.0").unwrap());
+        assert!(!needs_mapping_type("8.0.0-SNAPSHOT").unwrap());
+        assert!(needs_mapping_type("").is_err());
+        assert!(needs_mapping_type("v7").is_err());
+    }
+
+    #[test]
+    fn endpoint_joins_the_base_path() {
+        assert_eq!(endpoint("https://gw/es", "logs/_search"), "https://gw/es/logs/_search");
+        assert_eq!(endpoint("https://gw/es/", "logs/_search"), "https://gw/es/logs/_search");
+        assert_eq!(
+            endpoint("https://gw/es/", "/logs/_search"),
+            "https://gw/es/logs/_search"
+        );
+    }
+
+    #[test]
+    fn endpoint_of_a_host_without_path() {
+        assert_eq!(
+            endpoint("http://localhost:9200", "_cat/indices"),
+            "http://localhost:9200/_cat/indices"
+        );
+        assert_eq!(
+            endpoint("http://localhost:9200/", "_cat/indices"),
+            "http://localhost:9200/_cat/indices"
+        );
+        assert_eq!(endpoint("http://localhost:9200", ""), "http://localhost:9200/");
+        assert_eq!(
+            endpoint("http://localhost:9200", "remote:logs/_search"),
+            "http://localhost:9200/remote:logs/_search"
+        );
+    }
+
+    #[test]
+    fn endpoint_keeps_the_query_string() {
+        assert_eq!(
+            endpoint("https://gw/es", "_cat/indices/logs*?format=json&bytes=b"),
+            "https://gw/es/_cat/indices/logs*?format=json&bytes=b"
+        );
+    }
+}
@@ -0,0 +1,35 @@
+use estunnel::common::Result;
+use jaq_interpret::{Ctx, Filter, FilterT, ParseCtx, RcIter, Val};
+use serde_json::Value;
+
+/// A compiled jq filter, which turns a doc into any number of docs.
+pub struct Jq {
+    filter: Filter,
+}
+
+impl Jq {
+    /// Compiles the filter with the jq standard library, so filters like `map` and `select` are available.
+    pub fn compile(code: &str) -> Result<Jq> {
+        let mut defs = ParseCtx::new(vec![]);
+        defs.insert_natives(jaq_core::core());
+        defs.insert_defs(jaq_std::std());
+        let (main, errs) = jaq_parse::parse(code, jaq_parse::main());
+        if let Some(e) = errs.first() {
+            return Err(format!("invalid jq filter `{}`: {}", code, e).into());
+        }
+        let filter = defs.compile(main.ok_or("empty jq filter")?);
+        if let Some((e, _)) = defs.errs.first() {
+            return Err(format!("invalid jq filter `{}`: {}", code, e).into());
+        }
+        Ok(Jq { filter })
+    }
+
+    /// Runs the filter on the doc, returning all its outputs or the first error.
+    pub fn run(&self, doc: Value) -> std::result::Result<Vec<Value>, String> {
+        let inputs = RcIter::new(std::iter::empty());
+        self.filter
+            .run((Ctx::new([], &inputs), Val::from(doc)))
+            .map(|out| out.map(Value::from).map_err(|e| e.to_string()))
+            .collect()
+    }
+}
@@ -0,0 +1,16 @@
+//! Scroll engine of estunnel, which pulls docs out of an elasticsearch index with sliced scrolls.
+//!
+//! ```no_run
+//! use estunnel::scroll::{pull_stream, PullConfig};
+//!
+//! # fn main() -> estunnel::common::Result<()> {
+//! for doc in pull_stream(PullConfig::new("http://localhost:9200", "my-index"))? {
+//!     println!("{}", doc?);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+pub mod common;
+pub mod elastic;
+pub mod query;
+pub mod scroll;
@@ -1,15 +1,32 @@
 mod cli;
 mod cmd;
-mod common;
-mod elastic;
+mod coerce;
+mod date;
+mod diff;
+mod jq;
+mod output;
+mod partition;
+mod progress;
+mod signal;
+mod sni;
+mod transform;
+mod uds;
+mod watermark;
 
 use cli::*;
-use common::*;
+use estunnel::common::*;
 
 fn main() -> Result<()> {
     match Opt::from_args() {
+        Opt::Agg(agg) => cmd::agg::agg(agg),
+        Opt::CompleteIndices(complete) => cmd::completion::complete_indices(complete),
         Opt::Completion(completion) => cmd::completion::completion(completion),
+        Opt::Indices(indices) => cmd::indices::indices(indices),
+        Opt::Probe(probe) => cmd::probe::probe(probe),
         Opt::Pull(pull) => cmd::pull::pull(pull),
+        Opt::Reindex(reindex) => cmd::reindex::reindex(reindex),
+        Opt::Run(run) => cmd::run::run(run),
         Opt::Update => cmd::update::update(),
+        Opt::Validate(validate) => cmd::validate::validate(validate),
     }
 }
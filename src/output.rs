@@ -0,0 +1,612 @@
+use estunnel::common::Result;
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use openssl::hash::{Hasher, MessageDigest};
+use serde::de::IgnoredAny;
+use std::ffi::OsString;
+use std::fmt::Display;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::mem;
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Compression::None),
+            "gzip" => Ok(Compression::Gzip),
+            "zstd" => Ok(Compression::Zstd),
+            s => Err(format!("expect one of none, gzip and zstd, got `{}`", s)),
+        }
+    }
+}
+
+impl Compression {
+    pub fn extension(self) -> Option<&'static str> {
+        match self {
+            Compression::None => None,
+            Compression::Gzip => Some("gz"),
+            Compression::Zstd => Some("zst"),
+        }
+    }
+
+    /// Compression of a file by its extension.
+    pub fn of_path(path: &Path) -> Compression {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("gz") => Compression::Gzip,
+            Some("zst") => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+
+    /// Checks that `level` is one the algorithm supports, 0-9 for gzip and 1-22 for zstd.
+    pub fn check_level(self, level: u32) -> std::result::Result<(), String> {
+        let (name, range) = match self {
+            Compression::None => return Err("--compress-level needs --compress gzip or zstd".to_string()),
+            Compression::Gzip => ("gzip", 0..=9),
+            Compression::Zstd => ("zstd", 1..=22),
+        };
+        match range.contains(&level) {
+            true => Ok(()),
+            false => Err(format!(
+                "{} supports levels {} to {}, got {}",
+                name,
+                range.start(),
+                range.end(),
+                level
+            )),
+        }
+    }
+}
+
+/// Hash algorithm of the checksum files written next to the output files.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Checksum {
+    Sha256,
+    Sha512,
+    Md5,
+}
+
+impl FromStr for Checksum {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "sha256" => Ok(Checksum::Sha256),
+            "sha512" => Ok(Checksum::Sha512),
+            "md5" => Ok(Checksum::Md5),
+            s => Err(format!("expect one of sha256, sha512 and md5, got `{}`", s)),
+        }
+    }
+}
+
+impl Checksum {
+    /// Extension of the checksum file, also the name of the tool checking it like `sha256sum -c`.
+    fn extension(self) -> &'static str {
+        match self {
+            Checksum::Sha256 => "sha256",
+            Checksum::Sha512 => "sha512",
+            Checksum::Md5 => "md5",
+        }
+    }
+
+    fn hasher(self) -> io::Result<Hasher> {
+        let digest = match self {
+            Checksum::Sha256 => MessageDigest::sha256(),
+            Checksum::Sha512 => MessageDigest::sha512(),
+            Checksum::Md5 => MessageDigest::md5(),
+        };
+        Ok(Hasher::new(digest)?)
+    }
+}
+
+/// How the files of an output are written.
+#[derive(Debug, Clone, Copy)]
+pub struct Encoding {
+    pub compression: Compression,
+    /// Level of the encoder, the default of the algorithm if none.
+    pub level:       Option<u32>,
+    /// Algorithm of the checksum file written for each file.
+    pub checksum:    Option<Checksum>,
+}
+
+/// Layout of the docs in the output.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// A json line per doc.
+    Json,
+    /// An action line of the `_bulk` API followed by the source line for each doc.
+    Bulk,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Format::Json),
+            "bulk" => Ok(Format::Bulk),
+            s => Err(format!("expect one of json and bulk, got `{}`", s)),
+        }
+    }
+}
+
+/// Appends the extension of the compression to the path, unless it has it already or is a device like `/dev/stdout`.
+pub fn output_path(path: PathBuf, compression: Compression) -> PathBuf {
+    match compression.extension() {
+        Some(ext) if !path.starts_with("/dev") && path.extension().is_none_or(|e| e != ext) => {
+            let mut path = OsString::from(path);
+            path.push(".");
+            path.push(ext);
+            path.into()
+        }
+        _ => path,
+    }
+}
+
+/// File name pattern of the outputs of the slices like `export-{index}-{slice}.jsonl`.
+///
+/// `{slice}` is the slice number padded to the width of the slice count, `{date}` the UTC date of the start of the
+/// pull like `2023-01-31`.
+#[derive(Debug, Clone)]
+pub struct OutputTemplate(String);
+
+impl FromStr for OutputTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut rest = s;
+        let mut has_slice = false;
+        while let Some(i) = rest.find('{') {
+            let end = rest[i..]
+                .find('}')
+                .ok_or_else(|| format!("unclosed placeholder in `{}`", s))?;
+            match &rest[i + 1..i + end] {
+                "slice" => has_slice = true,
+                "index" | "date" => {}
+                "shard" => return Err("placeholder {shard} is not supported, as slices do not map to shards".into()),
+                name => {
+                    return Err(format!(
+                        "unknown placeholder {{{}}}, expect one of {{index}}, {{slice}} and {{date}}",
+                        name
+                    ))
+                }
+            }
+            rest = &rest[i + end + 1..];
+        }
+        // without it all slices would write to the same file
+        if !has_slice {
+            return Err(format!("the template needs a {{slice}} placeholder, got `{}`", s));
+        }
+        Ok(OutputTemplate(s.to_string()))
+    }
+}
+
+impl OutputTemplate {
+    /// Whether the paths contain the index name, so outputs of different indices do not collide.
+    pub fn has_index(&self) -> bool {
+        self.0.contains("{index}")
+    }
+
+    /// Path of the output of each slice.
+    pub fn paths(&self, index: &str, slice: u64, compression: Compression) -> Vec<PathBuf> {
+        // patterns like `logs-*,metrics` would make odd file names
+        let index: String = index
+            .chars()
+            .map(|c| match c {
+                '*' | ',' | '/' => '_',
+                c => c,
+            })
+            .collect();
+        let date = utc_date(SystemTime::now());
+        let width = slice.to_string().len();
+        (1..=slice)
+            .map(|n| {
+                let path = self
+                    .0
+                    .replace("{index}", &index)
+                    .replace("{date}", &date)
+                    .replace("{slice}", &format!("{:0width$}", n, width = width));
+                output_path(path.into(), compression)
+            })
+            .collect()
+    }
+}
+
+/// Formats the date of the time in UTC like `2023-01-31`.
+fn utc_date(time: SystemTime) -> String {
+    let days = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() / 86400) as i64;
+    let (year, month, day) = civil_from_days(days);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Year, month and day of the days since 1970-01-01, see http://howardhinnant.github.io/date_algorithms.html
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month as u32, day as u32)
+}
+
+/// File of an output, hashing the bytes written to it for a checksum.
+struct Target {
+    file:   File,
+    path:   PathBuf,
+    hasher: Option<(Checksum, Hasher)>,
+}
+
+impl Write for Target {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.file.write(buf)?;
+        if let Some((_, hasher)) = &mut self.hasher {
+            hasher.update(&buf[..n])?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Target {
+    /// Flushes the file and writes its checksum to a file named like `out.jsonl.gz.sha256`, in the format of
+    /// `sha256sum` so it can be checked with `sha256sum -c`.
+    fn close(mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if let Some((checksum, mut hasher)) = self.hasher {
+            let digest = hasher.finish()?;
+            let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+            let name = self.path.file_name().unwrap_or_default().to_string_lossy();
+            let mut path = OsString::from(&self.path);
+            path.push(".");
+            path.push(checksum.extension());
+            std::fs::write(path, format!("{}  {}\n", hex, name))?;
+        }
+        Ok(())
+    }
+}
+
+enum Encoder {
+    Plain(Target),
+    Gzip(GzEncoder<Target>),
+    Zstd(zstd::Encoder<'static, Target>),
+    /// Discards the docs, for a benchmark.
+    Sink(io::Sink),
+}
+
+impl Write for Encoder {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Encoder::Plain(w) => w.write(buf),
+            Encoder::Gzip(w) => w.write(buf),
+            Encoder::Zstd(w) => w.write(buf),
+            Encoder::Sink(w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Encoder::Plain(w) => w.flush(),
+            Encoder::Gzip(w) => w.flush(),
+            Encoder::Zstd(w) => w.flush(),
+            Encoder::Sink(w) => w.flush(),
+        }
+    }
+}
+
+/// Thresholds to roll the output over to a new file, the bytes are counted before compression.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Rotation {
+    pub bytes: Option<u64>,
+    pub docs:  Option<u64>,
+}
+
+impl Rotation {
+    pub fn enabled(self) -> bool {
+        self.bytes.is_some() || self.docs.is_some()
+    }
+}
+
+/// Path of the `n`th file of a rotated output, like `out.00002.jsonl.gz` for `out.jsonl.gz`.
+fn part_path(path: &Path, compression: Compression, n: u64) -> PathBuf {
+    let name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+    let suffix = compression
+        .extension()
+        .map(|ext| format!(".{}", ext))
+        .unwrap_or_default();
+    let base = name.strip_suffix(&suffix).unwrap_or(&name);
+    let (stem, ext) = match base.rfind('.') {
+        Some(i) if i > 0 => base.split_at(i),
+        _ => (base, ""),
+    };
+    path.with_file_name(format!("{}.{:05}{}{}", stem, n, ext, suffix))
+}
+
+/// Buffered writer of the output file, which must be finished to complete the compressed stream.
+///
+/// With rotation the docs are written to numbered files, a new one is started once the current one is full. Each file
+/// is finished on its own, so compressed files concatenate into a valid multi-member stream.
+pub struct Output {
+    inner:       BufWriter<Encoder>,
+    /// Whether the output is a pipe or a FIFO, whose reader should get docs as soon as they arrive.
+    pipe:        bool,
+    path:        PathBuf,
+    encoding:    Encoding,
+    rotation:    Rotation,
+    /// Docs written to the current file.
+    docs:        u64,
+    /// Bytes written to the current file.
+    bytes:       u64,
+    /// Bytes written to all files.
+    total_bytes: u64,
+    /// Files written so far, the last one is the current file.
+    files:       Vec<PathBuf>,
+}
+
+impl Output {
+    pub fn create(path: &Path, encoding: Encoding, rotation: Rotation) -> io::Result<Output> {
+        let first = match rotation.enabled() {
+            true => part_path(path, encoding.compression, 1),
+            false => path.to_path_buf(),
+        };
+        let (inner, pipe) = open(&first, encoding, false)?;
+        Ok(Output {
+            inner,
+            pipe,
+            path: path.to_path_buf(),
+            encoding,
+            rotation,
+            docs: 0,
+            bytes: 0,
+            total_bytes: 0,
+            files: vec![first],
+        })
+    }
+
+    /// Output appending to the file, as another gzip member or zstd frame if compressed.
+    pub fn append(path: &Path, encoding: Encoding) -> io::Result<Output> {
+        let (inner, pipe) = open(path, encoding, true)?;
+        Ok(Output {
+            inner,
+            pipe,
+            path: path.to_path_buf(),
+            encoding,
+            rotation: Rotation::default(),
+            docs: 0,
+            bytes: 0,
+            total_bytes: 0,
+            files: vec![path.to_path_buf()],
+        })
+    }
+
+    /// Output which counts the docs and bytes but discards them, leaving no files.
+    pub fn sink() -> Output {
+        Output {
+            inner:       BufWriter::new(Encoder::Sink(io::sink())),
+            pipe:        false,
+            path:        PathBuf::new(),
+            encoding:    Encoding {
+                compression: Compression::None,
+                level:       None,
+                checksum:    None,
+            },
+            rotation:    Rotation::default(),
+            docs:        0,
+            bytes:       0,
+            total_bytes: 0,
+            files:       vec![],
+        }
+    }
+
+    /// Writes a doc as a line, rolling over to a new file first if the current one is full.
+    pub fn write_doc<T: Display + ?Sized>(&mut self, doc: &T) -> io::Result<()> {
+        let full = self.docs > 0
+            && (self.rotation.docs.is_some_and(|max| self.docs >= max)
+                || self.rotation.bytes.is_some_and(|max| self.bytes >= max));
+        if full {
+            let next = part_path(&self.path, self.encoding.compression, self.files.len() as u64 + 1);
+            let (inner, _) = open(&next, self.encoding, false)?;
+            finish(mem::replace(&mut self.inner, inner))?;
+            self.files.push(next);
+            self.docs = 0;
+            self.bytes = 0;
+        }
+        writeln!(self, "{}", doc)?;
+        self.docs += 1;
+        Ok(())
+    }
+
+    /// Flushes a completely written page if the output is a pipe, so its reader does not stall.
+    pub fn end_page(&mut self) -> io::Result<()> {
+        if self.pipe {
+            self.inner.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Bytes written to all files so far, before compression.
+    pub fn total_bytes(&self) -> u64 {
+        self.total_bytes
+    }
+
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    /// Flushes buffered data and writes the trailer of the compressed stream.
+    pub fn finish(self) -> io::Result<()> {
+        finish(self.inner)
+    }
+}
+
+fn open(path: &Path, encoding: Encoding, append: bool) -> io::Result<(BufWriter<Encoder>, bool)> {
+    let Encoding {
+        compression,
+        level,
+        checksum,
+    } = encoding;
+    let mut hasher = match checksum {
+        Some(checksum) => Some((checksum, checksum.hasher()?)),
+        None => None,
+    };
+    let file = match append {
+        true => {
+            // the checksum covers the whole file, so the bytes written before are hashed first
+            if let Some((_, hasher)) = &mut hasher {
+                io::copy(&mut File::open(path)?, hasher)?;
+            }
+            OpenOptions::new().append(true).open(path)?
+        }
+        false => File::create(path)?,
+    };
+    let pipe = file.metadata()?.file_type().is_fifo();
+    let file = Target {
+        file,
+        path: path.to_path_buf(),
+        hasher,
+    };
+    let encoder = match compression {
+        Compression::None => Encoder::Plain(file),
+        // defaults to 6
+        Compression::Gzip => Encoder::Gzip(GzEncoder::new(
+            file,
+            level.map_or_else(flate2::Compression::default, flate2::Compression::new),
+        )),
+        // 0 is the default of zstd, which is 3
+        Compression::Zstd => Encoder::Zstd(zstd::Encoder::new(file, level.unwrap_or(0) as i32)?),
+    };
+    Ok((BufWriter::new(encoder), pipe))
+}
+
+fn finish(inner: BufWriter<Encoder>) -> io::Result<()> {
+    match inner.into_inner().map_err(|e| e.into_error())? {
+        Encoder::Plain(w) => w.close(),
+        Encoder::Gzip(w) => w.finish()?.close(),
+        Encoder::Zstd(w) => w.finish()?.close(),
+        Encoder::Sink(mut w) => w.flush(),
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.bytes += n as u64;
+        self.total_bytes += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Result of reading back an output file.
+pub struct Verification {
+    pub lines:     u64,
+    /// Line numbers and errors of lines which are not valid json.
+    pub malformed: Vec<(u64, String)>,
+}
+
+/// Opens an output file, decompressing it as it is read.
+pub fn reader(path: &Path, compression: Compression) -> io::Result<Box<dyn Read>> {
+    let file = File::open(path)?;
+    Ok(match compression {
+        Compression::None => Box::new(file),
+        Compression::Gzip => Box::new(MultiGzDecoder::new(file)),
+        Compression::Zstd => Box::new(zstd::Decoder::new(file)?),
+    })
+}
+
+/// Reads back the output file and checks that every line is a json document.
+pub fn verify(path: &Path, compression: Compression) -> Result<Verification> {
+    let reader = reader(path, compression)?;
+    let mut res = Verification {
+        lines:     0,
+        malformed: vec![],
+    };
+    for line in BufReader::new(reader).lines() {
+        let line = line?;
+        res.lines += 1;
+        if let Err(e) = serde_json::from_str::<IgnoredAny>(&line) {
+            res.malformed.push((res.lines, e.to_string()));
+        }
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn part_number_goes_before_the_extensions() {
+        let part = |path: &str, compression, n| part_path(Path::new(path), compression, n);
+        assert_eq!(
+            part("out/docs.jsonl", Compression::None, 2),
+            Path::new("out/docs.00002.jsonl")
+        );
+        assert_eq!(
+            part("docs.jsonl.gz", Compression::Gzip, 12),
+            Path::new("docs.00012.jsonl.gz")
+        );
+        assert_eq!(
+            part("docs.json.zst", Compression::Zstd, 1),
+            Path::new("docs.00001.json.zst")
+        );
+        assert_eq!(part("docs", Compression::None, 3), Path::new("docs.00003"));
+        assert_eq!(part("docs.gz", Compression::Gzip, 3), Path::new("docs.00003.gz"));
+        // a leading dot does not start an extension
+        assert_eq!(part(".docs", Compression::None, 4), Path::new(".docs.00004"));
+    }
+
+    #[test]
+    fn gzip_parts_concatenate_into_one_stream() {
+        let dir = std::env::temp_dir().join(format!("estunnel-gzip-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let encoding = Encoding {
+            compression: Compression::Gzip,
+            level:       None,
+            checksum:    None,
+        };
+        let rotation = Rotation {
+            bytes: None,
+            docs:  Some(2),
+        };
+        let mut output = Output::create(&dir.join("out.jsonl.gz"), encoding, rotation).unwrap();
+        for n in 0..4 {
+            output.write_doc(&format!("{{\"n\":{}}}", n)).unwrap();
+        }
+        let files = output.files().to_vec();
+        output.finish().unwrap();
+        assert_eq!(files.len(), 2);
+
+        // like `cat out.*.jsonl.gz | gunzip`
+        let concatenated: Vec<u8> = files.iter().flat_map(|file| fs::read(file).unwrap()).collect();
+        let mut text = String::new();
+        MultiGzDecoder::new(&concatenated[..])
+            .read_to_string(&mut text)
+            .unwrap();
+        assert_eq!(text, "{\"n\":0}\n{\"n\":1}\n{\"n\":2}\n{\"n\":3}\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
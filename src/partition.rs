@@ -0,0 +1,181 @@
+use crate::date::{self, Date, DateFormat, Token};
+use crate::output::{output_path, Encoding, Output};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Value of the directives of the partition of the docs without a date.
+const NULL: &str = "__null__";
+
+/// Name pattern of the partition directories like `dt=%Y-%m-%d`, with the `%Y`, `%m`, `%d` and `%H` of the date in
+/// UTC, `%%` for a `%`, and `/` between nested directories.
+#[derive(Debug, Clone)]
+pub struct PartitionFormat(String);
+
+impl FromStr for PartitionFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        DateFormat::from_str(s)?;
+        if s.starts_with('/') || s.split('/').any(|dir| dir.is_empty() || dir == "..") {
+            return Err(format!("the format must name relative directories, got `{}`", s));
+        }
+        Ok(PartitionFormat(s.to_string()))
+    }
+}
+
+impl PartitionFormat {
+    /// Name of the partition of the date, or of missing dates as `__null__` in place of the directives of each
+    /// directory.
+    fn name(&self, date: Option<&Date>) -> String {
+        self.0
+            .split('/')
+            .map(|dir| match date {
+                Some(date) => date::format(dir, date),
+                None => null_dir(dir),
+            })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+}
+
+/// Replaces the directives of the directory, from the first to the last, with `__null__`.
+fn null_dir(dir: &str) -> String {
+    let tokens = date::tokens(dir);
+    let is_directive = |token: &Token| matches!(token, Token::Directive(_));
+    let literal = |tokens: &[Token]| {
+        tokens
+            .iter()
+            .filter_map(|token| match token {
+                Token::Literal(c) => Some(*c),
+                Token::Directive(_) => None,
+            })
+            .collect::<String>()
+    };
+    match (
+        tokens.iter().position(is_directive),
+        tokens.iter().rposition(is_directive),
+    ) {
+        (Some(first), Some(last)) => format!("{}{}{}", literal(&tokens[..first]), NULL, literal(&tokens[last + 1..])),
+        _ => literal(&tokens),
+    }
+}
+
+/// Outputs of the partitions under a directory like `out/dt=2023-01-31/part.jsonl`, keeping at most `max_open` of
+/// them open.
+pub struct Partitions {
+    field:       String,
+    /// Whether the docs are whole hits with the field in `_source`.
+    hits:        bool,
+    format:      PartitionFormat,
+    dir:         PathBuf,
+    encoding:    Encoding,
+    max_open:    usize,
+    /// Open outputs by partition name, with the number of the doc they last got.
+    open:        HashMap<String, (u64, Output)>,
+    docs:        u64,
+    /// Bytes written to the outputs closed so far.
+    closed:      u64,
+    /// Files of all partitions in the order they were created.
+    files:       Vec<PathBuf>,
+    /// Docs without a date in the field.
+    pub undated: Arc<AtomicU64>,
+}
+
+impl Partitions {
+    pub fn new(
+        field: String,
+        hits: bool,
+        format: PartitionFormat,
+        dir: PathBuf,
+        encoding: Encoding,
+        max_open: usize,
+    ) -> Partitions {
+        Partitions {
+            field,
+            hits,
+            format,
+            dir,
+            encoding,
+            max_open: max_open.max(1),
+            open: HashMap::new(),
+            docs: 0,
+            closed: 0,
+            files: vec![],
+            undated: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Output of the partition of the doc, opening it and closing the least recently used one if needed.
+    pub fn output(&mut self, doc: &Value) -> io::Result<&mut Output> {
+        let source = match self.hits {
+            true => &doc["_source"],
+            false => doc,
+        };
+        let date = date::field(source, &self.field).and_then(Date::parse);
+        if date.is_none() {
+            self.undated.fetch_add(1, Ordering::Relaxed);
+        }
+        let name = self.format.name(date.as_ref());
+        self.docs += 1;
+        if !self.open.contains_key(&name) {
+            if self.open.len() >= self.max_open {
+                self.close_least_recent()?;
+            }
+            let path = output_path(self.dir.join(&name).join("part.jsonl"), self.encoding.compression);
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir).map_err(|e| {
+                    io::Error::new(
+                        e.kind(),
+                        format!("cannot create partition directory {}: {}", dir.display(), e),
+                    )
+                })?;
+            }
+            // a partition closed before is appended to, which a compressed file allows as another member or frame
+            let output = match self.files.contains(&path) {
+                true => Output::append(&path, self.encoding)?,
+                false => {
+                    let output = Output::create(&path, self.encoding, Default::default())?;
+                    self.files.push(path);
+                    output
+                }
+            };
+            self.open.insert(name.clone(), (self.docs, output));
+        }
+        let (used, output) = self.open.get_mut(&name).unwrap();
+        *used = self.docs;
+        Ok(output)
+    }
+
+    fn close_least_recent(&mut self) -> io::Result<()> {
+        let name = match self.open.iter().min_by_key(|(_, (used, _))| *used) {
+            Some((name, _)) => name.clone(),
+            None => return Ok(()),
+        };
+        let (_, output) = self.open.remove(&name).unwrap();
+        self.closed += output.total_bytes();
+        output.finish()
+    }
+
+    /// Bytes written to all partitions so far, before compression.
+    pub fn total_bytes(&self) -> u64 {
+        self.closed + self.open.values().map(|(_, output)| output.total_bytes()).sum::<u64>()
+    }
+
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    /// Finishes the open outputs.
+    pub fn finish(self) -> io::Result<()> {
+        for (_, output) in self.open.into_values() {
+            output.finish()?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,382 @@
+use estunnel::scroll::Observer;
+use indicatif::{FormattedDuration, MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::cmp::min;
+use std::io::{self, IsTerminal};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long a slice may wait for the cluster before its bar shows the time it has been waiting.
+const STALL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BarStyle {
+    Bar,
+    Spinner,
+    /// One bar summing up all slices instead of a bar per slice.
+    Aggregate,
+    /// No bars, but a status line printed every refresh interval, which suits log files.
+    Plain,
+}
+
+impl FromStr for BarStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bar" => Ok(BarStyle::Bar),
+            "spinner" => Ok(BarStyle::Spinner),
+            "aggregate" => Ok(BarStyle::Aggregate),
+            "plain" => Ok(BarStyle::Plain),
+            s => Err(format!("expect one of bar, spinner, aggregate and plain, got `{}`", s)),
+        }
+    }
+}
+
+impl BarStyle {
+    /// Default refresh interval in milliseconds.
+    pub fn default_refresh(self) -> u64 {
+        match self {
+            BarStyle::Plain => 10_000,
+            _ => 100,
+        }
+    }
+
+    fn style(self, template: &str) -> ProgressStyle {
+        match self {
+            BarStyle::Spinner => ProgressStyle::default_spinner().template(&template.replace("{bar:50}", "{spinner}")),
+            _ => ProgressStyle::default_bar().template(template).progress_chars("##-"),
+        }
+    }
+
+    /// Style of a slice bar whose scroll is running.
+    pub fn running(self) -> ProgressStyle {
+        self.style(
+            "{prefix:.bold} {elapsed_precise} {bar:50} {percent:>3}% {pos}/{len} ETA {eta_precise} {msg:.yellow.bold}",
+        )
+    }
+
+    /// Style of a slice bar which has fetched all its docs.
+    pub fn finished(self) -> ProgressStyle {
+        self.style(
+            "{prefix:.bold} {elapsed_precise} {bar:50} {percent:>3}% {pos}/{len} ETA {eta_precise} {msg:.green.bold}",
+        )
+    }
+
+    /// Style of the task bar after the output is done.
+    pub fn task_finished(self) -> ProgressStyle {
+        self.style("{prefix:.bold} {elapsed_precise} {bar:50} {percent:>3}% {pos}/{len} ETA {eta_precise}")
+    }
+}
+
+/// Progress bars of the slices and the overall task.
+pub struct Progress {
+    mpb:       MultiProgress,
+    style:     BarStyle,
+    /// Refresh interval in milliseconds.
+    refresh:   u64,
+    bars:      Vec<ProgressBar>,
+    /// The bar summing up all slices in aggregate style.
+    aggregate: Option<ProgressBar>,
+    limit:     Option<u64>,
+}
+
+impl Progress {
+    pub fn new(style: BarStyle, refresh: Option<u64>) -> Progress {
+        let refresh = refresh.unwrap_or_else(|| style.default_refresh()).max(1);
+        let target = || ProgressDrawTarget::stderr_with_hz(1000 / refresh.min(1000));
+        let (mpb, aggregate) = match style {
+            BarStyle::Plain => (MultiProgress::with_draw_target(ProgressDrawTarget::hidden()), None),
+            BarStyle::Aggregate => {
+                let pb = ProgressBar::with_draw_target(0, target());
+                pb.set_style(BarStyle::Bar.running());
+                pb.set_prefix("Total:");
+                pb.set_message("Running...");
+                pb.enable_steady_tick(refresh);
+                (MultiProgress::with_draw_target(ProgressDrawTarget::hidden()), Some(pb))
+            }
+            _ => (MultiProgress::with_draw_target(target()), None),
+        };
+        Progress {
+            mpb,
+            style,
+            refresh,
+            bars: vec![],
+            aggregate,
+            limit: None,
+        }
+    }
+
+    fn add(&self, len: u64) -> ProgressBar {
+        match self.style {
+            BarStyle::Plain | BarStyle::Aggregate => ProgressBar::hidden(),
+            _ => {
+                let pb = self.mpb.add(ProgressBar::new(len));
+                pb.set_draw_delta(1_000_000);
+                pb.enable_steady_tick(self.refresh);
+                pb
+            }
+        }
+    }
+
+    /// Adds a bar for each of the slices, logging their start and end if `verbose`.
+    pub fn add_slices(&mut self, slice: u64, verbose: bool) -> SliceBars {
+        self.add_only_slices(&(0..slice).collect::<Vec<_>>(), slice, verbose)
+    }
+
+    /// Like [`Progress::add_slices`], but only for the slices with these ids out of `slice`.
+    pub fn add_only_slices(&mut self, ids: &[u64], slice: u64, verbose: bool) -> SliceBars {
+        let slice_num_width = slice.to_string().len();
+        for job_id in ids.iter().map(|id| id + 1) {
+            let pb = self.add(1);
+            pb.set_style(
+                self.style
+                    .style("{prefix:.bold} {elapsed_precise} {bar:50} {percent:>3}% {msg:.yellow.bold}"),
+            );
+            pb.set_prefix(&format!("[{:0width$}/{}]", job_id, slice, width = slice_num_width));
+            pb.set_message("Waiting...");
+            self.bars.push(pb);
+        }
+        let statuses = Arc::new(self.bars.iter().map(|_| Mutex::new(None)).collect());
+        if self.style == BarStyle::Bar || self.style == BarStyle::Spinner {
+            let bars = self.bars.clone();
+            let statuses = Arc::downgrade(&statuses);
+            thread::spawn(move || tick_stalls(bars, statuses));
+        }
+        SliceBars {
+            retries: self.bars.iter().map(|_| AtomicU32::new(0)).collect(),
+            timings: self.bars.iter().map(|_| Mutex::new(Timing::default())).collect(),
+            statuses,
+            bars: self.bars.clone(),
+            job_ids: ids.iter().map(|id| id + 1).collect(),
+            style: self.style,
+            verbose,
+        }
+    }
+
+    pub fn add_task(&mut self, limit: u64) -> ProgressBar {
+        self.limit = Some(limit);
+        let pb = self.add(limit);
+        pb.set_style(
+            self.style
+                .style("{prefix:.blue.bold} {elapsed_precise} {bar:50} {percent:>3}% {pos}/{len} ETA {eta_precise}"),
+        );
+        pb.set_prefix("Task:");
+        pb
+    }
+
+    /// Blocks until all bars are finished. `total` is the sum of the total hits reported by the slices.
+    ///
+    /// In plain style a status line is printed every refresh interval meanwhile, in aggregate style the bar summing
+    /// up the slices is updated instead.
+    pub fn join(&self, total: &AtomicU64) {
+        if self.style != BarStyle::Plain && self.style != BarStyle::Aggregate {
+            self.mpb.join().expect("error joining progress threads");
+            return;
+        }
+        let started = Instant::now();
+        let mut updated = Instant::now();
+        loop {
+            let finished = self.bars.iter().filter(|pb| pb.is_finished()).count();
+            let done = finished == self.bars.len();
+            if done || updated.elapsed() >= Duration::from_millis(self.refresh) {
+                let total = total.load(Ordering::Relaxed);
+                let fetched: u64 = self.bars.iter().map(|pb| pb.position()).sum();
+                let (total, fetched) = match self.limit {
+                    Some(limit) => (min(total, limit), min(fetched, limit)),
+                    None => (total, fetched),
+                };
+                match &self.aggregate {
+                    Some(pb) => {
+                        pb.set_length(total);
+                        pb.set_position(fetched);
+                    }
+                    None => eprintln!(
+                        "[{}] {}/{} documents fetched, {}/{} slices finished",
+                        FormattedDuration(started.elapsed()),
+                        fetched,
+                        total,
+                        finished,
+                        self.bars.len()
+                    ),
+                }
+                updated = Instant::now();
+            }
+            if done {
+                if let Some(pb) = &self.aggregate {
+                    pb.set_style(BarStyle::Bar.finished());
+                    pb.finish_with_message("Finished.");
+                }
+                return;
+            }
+            thread::sleep(Duration::from_millis(min(100, self.refresh)));
+        }
+    }
+}
+
+/// Appends the time a slice has been waiting for the cluster to its message once it exceeds [`STALL`], so a slow
+/// request can be told from a hung process. Returns when all bars are finished or the slice bars are dropped.
+fn tick_stalls(bars: Vec<ProgressBar>, statuses: Weak<Vec<Mutex<Option<Status>>>>) {
+    while !bars.iter().all(|pb| pb.is_finished()) {
+        thread::sleep(Duration::from_secs(1));
+        let statuses = match statuses.upgrade() {
+            Some(statuses) => statuses,
+            None => return,
+        };
+        for (pb, status) in bars.iter().zip(statuses.iter()) {
+            if let Some(status) = &*status.lock().unwrap() {
+                let waiting = status.since.elapsed();
+                if waiting >= STALL && !status.paused && !pb.is_finished() {
+                    pb.set_message(&format!("{} waiting {}s", status.message, waiting.as_secs()));
+                }
+            }
+        }
+    }
+}
+
+/// Shows the progress of each slice on its bar.
+pub struct SliceBars {
+    bars:     Vec<ProgressBar>,
+    /// Number of each slice, counting the slices of the whole scroll from 1.
+    job_ids:  Vec<u64>,
+    /// Retries of each slice so far.
+    retries:  Vec<AtomicU32>,
+    timings:  Vec<Mutex<Timing>>,
+    /// Message of each running slice and when it last heard from the cluster.
+    statuses: Arc<Vec<Mutex<Option<Status>>>>,
+    style:    BarStyle,
+    verbose:  bool,
+}
+
+struct Status {
+    message: String,
+    since:   Instant,
+    /// Whether the slice is paused rather than waiting for the cluster.
+    paused:  bool,
+}
+
+#[derive(Default)]
+struct Timing {
+    started: Option<Instant>,
+    elapsed: Option<Duration>,
+}
+
+impl SliceBars {
+    /// Sets the message of the slice, which is waiting for the cluster from now on.
+    fn set_status(&self, slice_id: u64, message: String) {
+        self.bars[slice_id as usize].set_message(&message);
+        let since = Instant::now();
+        *self.statuses[slice_id as usize].lock().unwrap() = Some(Status {
+            message,
+            since,
+            paused: false,
+        });
+    }
+
+    /// Prints the docs and the time taken by each slice, uneven times hint at skewed data or a hot shard.
+    pub fn print_timings(&self) {
+        eprintln!("{:>8} {:>12} {:>10}", "Slice", "Documents", "Time");
+        for ((pb, timing), job_id) in self.bars.iter().zip(&self.timings).zip(&self.job_ids) {
+            let timing = timing.lock().unwrap();
+            let elapsed = match (timing.started, timing.elapsed) {
+                (_, Some(elapsed)) => format!("{:.1}s", elapsed.as_secs_f64()),
+                (Some(_), None) => "unfinished".to_string(),
+                (None, None) => "not started".to_string(),
+            };
+            eprintln!("{:>8} {:>12} {:>10}", job_id, pb.position(), elapsed);
+        }
+    }
+}
+
+impl Observer for SliceBars {
+    fn starting(&self, slice_id: u64) {
+        self.timings[slice_id as usize].lock().unwrap().started = Some(Instant::now());
+        self.set_status(slice_id, "Starting...".to_string());
+    }
+
+    fn retrying(&self, slice_id: u64, retries: u32) {
+        self.retries[slice_id as usize].store(retries, Ordering::Relaxed);
+        self.set_status(slice_id, format!("Retrying ({})...", retries));
+    }
+
+    fn started(&self, slice_id: u64, total: u64) {
+        let pb = &self.bars[slice_id as usize];
+        let message = match self.retries[slice_id as usize].load(Ordering::Relaxed) {
+            0 => "Running...".to_string(),
+            1 => "Running (1 retry)...".to_string(),
+            n => format!("Running ({} retries)...", n),
+        };
+        self.set_status(slice_id, message);
+        pb.set_style(self.style.running());
+        if self.verbose {
+            self.log(
+                slice_id,
+                &format!(
+                    "Slice[{}]: started with {} hits",
+                    self.job_ids[slice_id as usize], total
+                ),
+            );
+        }
+    }
+
+    fn fetched(&self, slice_id: u64, count: u64, total: u64) {
+        let pb = &self.bars[slice_id as usize];
+        pb.set_length(total);
+        pb.inc(count);
+        if let Some(status) = &mut *self.statuses[slice_id as usize].lock().unwrap() {
+            if status.since.elapsed() >= STALL {
+                pb.set_message(&status.message);
+            }
+            status.since = Instant::now();
+        }
+    }
+
+    fn paused(&self, slice_id: u64, paused: bool) {
+        let pb = &self.bars[slice_id as usize];
+        if let Some(status) = &mut *self.statuses[slice_id as usize].lock().unwrap() {
+            status.paused = paused;
+            status.since = Instant::now();
+            match paused {
+                true => pb.set_message("Paused"),
+                false => pb.set_message(&status.message),
+            }
+        }
+    }
+
+    fn finished(&self, slice_id: u64, ok: bool) {
+        let pb = &self.bars[slice_id as usize];
+        let mut timing = self.timings[slice_id as usize].lock().unwrap();
+        timing.elapsed = timing.started.map(|started| started.elapsed());
+        drop(timing);
+        self.statuses[slice_id as usize].lock().unwrap().take();
+        if !ok {
+            pb.finish_at_current_pos();
+            return;
+        }
+        if self.verbose {
+            self.log(
+                slice_id,
+                &format!(
+                    "Slice[{}]: finished with {} documents",
+                    self.job_ids[slice_id as usize],
+                    pb.position()
+                ),
+            );
+        }
+        pb.set_length(pb.position()); // adjust length
+        pb.set_style(self.style.finished());
+        pb.finish_with_message("Finished.");
+    }
+
+    /// Prints a message above the progress bars, or to stderr if they are not drawn.
+    fn log(&self, slice_id: u64, msg: &str) {
+        let pb = &self.bars[slice_id as usize];
+        if io::stderr().is_terminal() && !pb.is_hidden() {
+            pb.println(msg);
+        } else {
+            eprintln!("{}", msg);
+        }
+    }
+}
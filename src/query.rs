@@ -0,0 +1,42 @@
+use crate::common::Result;
+use serde_json::{json, Map, Value};
+
+/// Checks that the query is a json object, the only valid root of a search body.
+pub fn check_query(query: &Value) -> Result<()> {
+    let kind = match query {
+        Value::Object(_) => return Ok(()),
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+    };
+    Err(format!(
+        "the query must be a json object like `{{\"query\": {{...}}}}`, got {}",
+        kind
+    )
+    .into())
+}
+
+/// Restricts the query to docs whose `field` is within `[since, until)`.
+///
+/// Bounds are passed to elasticsearch as they are, so both dates and date math like `now-1d` work.
+pub fn filter_time_range(query: &mut Value, field: &str, since: Option<&str>, until: Option<&str>) {
+    let mut range = Map::new();
+    if let Some(since) = since {
+        range.insert("gte".into(), json!(since));
+    }
+    if let Some(until) = until {
+        range.insert("lt".into(), json!(until));
+    }
+    add_filter(query, json!({ "range": { field: range } }));
+}
+
+/// Adds a filter clause to the query body, the original query (if any) is kept as a `must` clause.
+pub fn add_filter(query: &mut Value, filter: Value) {
+    let inner = query["query"].take();
+    query["query"] = match inner {
+        Value::Null => json!({ "bool": { "filter": [filter] } }),
+        inner => json!({ "bool": { "must": [inner], "filter": [filter] } }),
+    };
+}
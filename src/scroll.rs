@@ -0,0 +1,1049 @@
+use crate::common::{Permit, RequestLimit, Result};
+use crate::elastic::*;
+use crate::query::check_query;
+use crossbeam::crossbeam_channel::{self, Receiver, SendTimeoutError, Sender};
+use reqwest::blocking::{Client, Response};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::cmp::{max, min};
+use std::collections::{HashSet, VecDeque};
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::mem;
+use std::path::Path;
+use std::result;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Lower bound of the batch size when it is reduced after timeouts.
+const MIN_BATCH: u64 = 10;
+/// Max attempts of opening a scroll context which the cluster refuses because too many are open, or of fetching a
+/// page which fails for a transient reason.
+const MAX_ATTEMPTS: u32 = 5;
+/// Upper bound of the delay between retries.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Settings of a pull, which the options of `estunnel pull` are turned into.
+pub struct PullConfig {
+    pub host:                  String,
+    pub no_proxy:              bool,
+    /// Proxy all requests go through, like the tunnel of `--tls-sni`.
+    pub proxy:                 Option<String>,
+    /// Extra http headers sent with every request.
+    pub headers:               Vec<(String, String)>,
+    /// Connection pool of the client shared by all slices.
+    pub pool:                  PoolOptions,
+    pub index:                 String,
+    pub user:                  Option<String>,
+    pub pass:                  Option<String>,
+    /// Body of the search request.
+    pub query:                 Value,
+    /// Scroll slice count.
+    pub slice:                 u64,
+    /// Ids of the slices to pull, from 0 below `slice`, all of them if empty. Observer events and pages refer to
+    /// the slices by their position in this list.
+    pub only_slices:           Vec<u64>,
+    /// Indices scrolled by one slice each instead of slicing the scroll of `index`, `slice` is their count then.
+    pub indices:               Vec<String>,
+    /// Max slices scrolling at the same time, all of them if none.
+    pub max_scroll_contexts:   Option<u64>,
+    /// Limit of the requests in flight, which may be shared with other requests to the cluster.
+    pub request_limit:         Option<Arc<RequestLimit>>,
+    /// Page size of the scrolls.
+    pub batch:                 u64,
+    /// Extra query string parameters of the initial search request.
+    pub params:                Vec<(String, String)>,
+    pub ttl:                   Ttl,
+    /// Clear the scroll of each slice once it is done, instead of leaving its context open until the ttl expires.
+    pub clear_scroll:          bool,
+    pub kind:                  DocKind,
+    /// Parts of each hit to merge into its doc, see [`parse_response`].
+    pub extras:                Extras,
+    /// Abort the pull if any shard fails instead of warning about it.
+    pub fail_on_shard_failure: bool,
+    /// Stop all slices once any of them fails, instead of letting the others finish.
+    pub fail_fast:             bool,
+    /// Skip docs larger than this many bytes.
+    pub max_doc_bytes:         Option<usize>,
+    /// Give up a slice after this many retries in total, however they were caused.
+    pub max_retries:           Option<u32>,
+    /// Retries allowed to all slices together, the pull stops once they are used up.
+    pub retry_budget:          Option<u64>,
+    /// Where skipped docs are recorded.
+    pub error_file:            Option<Arc<ErrorFile>>,
+    /// Format error responses of the cluster on several lines instead of as the raw body.
+    pub pretty_errors:         bool,
+}
+
+impl PullConfig {
+    /// Creates a config pulling the `_source` of all docs of the index with a single slice.
+    pub fn new(host: impl Into<String>, index: impl Into<String>) -> PullConfig {
+        PullConfig {
+            host:                  host.into(),
+            no_proxy:              false,
+            proxy:                 None,
+            headers:               vec![],
+            pool:                  PoolOptions::default(),
+            index:                 index.into(),
+            user:                  None,
+            pass:                  None,
+            query:                 json!({ "query": { "match_all": {} } }),
+            slice:                 1,
+            only_slices:           vec![],
+            indices:               vec![],
+            max_scroll_contexts:   None,
+            request_limit:         None,
+            batch:                 1000,
+            params:                vec![],
+            ttl:                   "1m".parse().unwrap(),
+            clear_scroll:          true,
+            kind:                  DocKind::Source,
+            extras:                Extras::default(),
+            fail_on_shard_failure: false,
+            fail_fast:             false,
+            max_doc_bytes:         None,
+            max_retries:           None,
+            retry_budget:          None,
+            error_file:            None,
+            pretty_errors:         false,
+        }
+    }
+}
+
+/// Scroll keep alive in the time format of elasticsearch, like `30s` or `5m`.
+#[derive(Debug, Clone)]
+pub struct Ttl {
+    value:        String,
+    pub duration: Duration,
+}
+
+impl FromStr for Ttl {
+    type Err = String;
+
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        let s = s.trim();
+        let i = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let (n, unit) = s.split_at(i);
+        let n: u64 = match n.parse() {
+            Ok(0) | Err(_) => return Err(format!("expect a positive duration like `30s` or `5m`, got `{}`", s)),
+            Ok(n) => n,
+        };
+        let duration = match unit {
+            "d" => Duration::from_secs(n.saturating_mul(24 * 3600)),
+            "h" => Duration::from_secs(n.saturating_mul(3600)),
+            "m" => Duration::from_secs(n.saturating_mul(60)),
+            "s" => Duration::from_secs(n),
+            "ms" => Duration::from_millis(n),
+            "micros" => Duration::from_micros(n),
+            "nanos" => Duration::from_nanos(n),
+            "" => return Err(format!("missing time unit in `{}`, e.g. `{}m`", s, n)),
+            _ => {
+                return Err(format!(
+                    "unknown time unit `{}`, expect one of d, h, m, s, ms, micros and nanos",
+                    unit
+                ))
+            }
+        };
+        Ok(Ttl {
+            value: s.to_string(),
+            duration,
+        })
+    }
+}
+
+impl fmt::Display for Ttl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.value)
+    }
+}
+
+/// Message sent from slice workers to the consumer of a pull.
+pub enum Page<T> {
+    /// A batch of docs fetched by the slice.
+    Docs(u64, Vec<T>),
+    /// The slice will not send any more docs.
+    Done(u64),
+}
+
+/// Receives progress events of the slices. All methods do nothing by default except `log`, which prints to stderr.
+pub trait Observer: Send + Sync {
+    /// The slice is about to open its scroll.
+    fn starting(&self, _slice_id: u64) {}
+
+    /// The slice is about to retry a failed request for the `retries`th time.
+    fn retrying(&self, _slice_id: u64, _retries: u32) {}
+
+    /// The scroll of the slice is open and matches `total` docs.
+    fn started(&self, _slice_id: u64, _total: u64) {}
+
+    /// The slice fetched `count` more docs.
+    fn fetched(&self, _slice_id: u64, _count: u64, _total: u64) {}
+
+    /// The slice has stopped requesting pages as the pull is paused, or has resumed unless `paused`.
+    fn paused(&self, _slice_id: u64, _paused: bool) {}
+
+    /// The slice has ended, with an error reported through [`Pull::errors`] unless `ok`.
+    fn finished(&self, _slice_id: u64, _ok: bool) {}
+
+    /// A warning or notice of the slice.
+    fn log(&self, _slice_id: u64, msg: &str) {
+        eprintln!("{}", msg);
+    }
+}
+
+impl Observer for () {}
+
+/// File of skipped docs, one json line per doc with the reason it was skipped.
+pub struct ErrorFile(Mutex<BufWriter<File>>);
+
+impl ErrorFile {
+    pub fn create(path: &Path) -> Result<ErrorFile> {
+        Ok(ErrorFile(Mutex::new(BufWriter::new(File::create(path)?))))
+    }
+
+    pub fn record<T: Serialize + ?Sized>(&self, reason: &str, doc: &T) -> io::Result<()> {
+        let mut file = self.0.lock().unwrap();
+        serde_json::to_writer(&mut *file, &json!({ "reason": reason, "doc": doc }))?;
+        writeln!(file)
+    }
+
+    pub fn flush(&self) -> io::Result<()> {
+        self.0.lock().unwrap().flush()
+    }
+}
+
+/// Counters of a pull, which are complete once all slices are done.
+#[derive(Default)]
+pub struct Stats {
+    /// Sum of the total hits reported by each slice, following the latest page of the slice.
+    pub reported: AtomicU64,
+    /// Count of slices which have got their first page, `reported` is known once all have.
+    pub started:  AtomicU64,
+    /// Count of docs skipped for being too large or containing invalid UTF-8.
+    pub skipped:  AtomicU64,
+    /// Count of requests retried by all slices.
+    pub retries:  AtomicU64,
+    /// Count of slices which have failed.
+    pub failed:   AtomicU64,
+    /// Milliseconds the cluster spent on each page, as reported by `took`.
+    pub took:     Mutex<Vec<u32>>,
+    /// Milliseconds from sending each page request to having parsed the response, which adds the network and the
+    /// transfer to `took`.
+    pub requests: Mutex<Vec<u32>>,
+}
+
+impl Stats {
+    fn record_page(&self, page: &SearchPage, started: Instant) {
+        self.took.lock().unwrap().push(page.took);
+        let elapsed = started.elapsed().as_millis().min(u32::MAX as u128) as u32;
+        self.requests.lock().unwrap().push(elapsed);
+    }
+}
+
+/// A running pull, whose pages must be received until `Page::Done` of every slice or until the receiver is dropped.
+pub struct Pull {
+    pub pages:  Receiver<Page<Source>>,
+    /// Errors of the slices, the channel is closed once all slices are done.
+    pub errors: Receiver<String>,
+    /// Set it to stop the slices after their current page.
+    pub stop:   Arc<AtomicBool>,
+    /// Set it to pause the slices after their current page and clear it to resume them. A paused slice still fetches
+    /// a page ahead whenever its scroll would expire otherwise.
+    pub pause:  Arc<AtomicBool>,
+    pub stats:  Arc<Stats>,
+}
+
+/// Settings shared by all slice workers of a pull task.
+struct Task {
+    host:                  String,
+    /// Shared by the slices, so they reuse the connections of each other.
+    client:                Client,
+    index:                 String,
+    user:                  Option<String>,
+    pass:                  Option<String>,
+    query:                 Value,
+    slice:                 u64,
+    /// Ids of the pulled slices by their position.
+    slice_ids:             Vec<u64>,
+    /// Index of each slice by its id, if the slices are whole indices.
+    indices:               Vec<String>,
+    batch:                 u64,
+    params:                Vec<(String, String)>,
+    ttl:                   Ttl,
+    clear_scroll:          bool,
+    kind:                  DocKind,
+    extras:                Extras,
+    request_limit:         Option<Arc<RequestLimit>>,
+    fail_on_shard_failure: bool,
+    fail_fast:             bool,
+    max_doc_bytes:         Option<usize>,
+    max_retries:           Option<u32>,
+    /// Retries left to all slices together.
+    retry_budget:          Option<AtomicU64>,
+    error_file:            Option<Arc<ErrorFile>>,
+    pretty_errors:         bool,
+    /// Warnings of the cluster logged so far, each is only logged once.
+    warned:                Mutex<HashSet<String>>,
+    finished:              Arc<AtomicBool>,
+    paused:                Arc<AtomicBool>,
+    stats:                 Arc<Stats>,
+    observer:              Arc<dyn Observer>,
+}
+
+impl Task {
+    /// Waits until a request may be sent, if the requests in flight are limited.
+    fn permit(&self) -> Option<Permit<'_>> {
+        self.request_limit.as_ref().map(|limit| limit.acquire())
+    }
+
+    /// Number of the slice in messages, counting the slices of the whole scroll from 1.
+    fn job_id(&self, slice_id: u64) -> u64 {
+        self.slice_ids[slice_id as usize] + 1
+    }
+
+    /// Logs the `Warning` headers of the response, like deprecations or capped results, unless logged before.
+    fn log_warnings(&self, slice_id: u64, res: &Response) {
+        for warning in warnings(res) {
+            if self.warned.lock().unwrap().insert(warning.clone()) {
+                let msg = format!("Warning[{}]: the cluster warns: {}", self.job_id(slice_id), warning);
+                self.observer.log(slice_id, &msg);
+            }
+        }
+    }
+}
+
+/// Starts pulling the slices in a thread pool, reporting their progress to `observer`.
+pub fn start(config: PullConfig, observer: Arc<dyn Observer>) -> Result<Pull> {
+    let PullConfig {
+        host,
+        no_proxy,
+        proxy,
+        headers,
+        pool,
+        index,
+        user,
+        pass,
+        query,
+        slice,
+        only_slices,
+        indices,
+        max_scroll_contexts,
+        request_limit,
+        batch,
+        params,
+        ttl,
+        clear_scroll,
+        kind,
+        extras,
+        fail_on_shard_failure,
+        fail_fast,
+        max_doc_bytes,
+        max_retries,
+        retry_budget,
+        error_file,
+        pretty_errors,
+    } = config;
+    check_query(&query)?;
+    if slice == 0 || max_scroll_contexts == Some(0) {
+        return Err("the pull needs at least one slice and one scroll context".into());
+    }
+    if !indices.is_empty() && indices.len() as u64 != slice {
+        return Err(format!("{} indices cannot be pulled by {} slices", indices.len(), slice).into());
+    }
+    let slice_ids = match only_slices.is_empty() {
+        true => (0..slice).collect(),
+        false => only_slices,
+    };
+    if let Some(id) = slice_ids.iter().find(|&&id| id >= slice) {
+        return Err(format!("slice id {} is out of the {} slices", id, slice).into());
+    }
+    let slices = slice_ids.len() as u64;
+    let client =
+        build_client(no_proxy, proxy.as_deref(), &headers, pool).map_err(|e| format!("Client error: {}", e))?;
+
+    let (res_tx, res_rx) = crossbeam_channel::bounded(slices as usize);
+    let (err_tx, err_rx) = crossbeam_channel::unbounded();
+    let task = Arc::new(Task {
+        host,
+        client,
+        index,
+        user,
+        pass,
+        query,
+        slice,
+        slice_ids,
+        indices,
+        batch,
+        params,
+        ttl,
+        clear_scroll,
+        kind,
+        extras,
+        request_limit,
+        fail_on_shard_failure,
+        fail_fast,
+        max_doc_bytes,
+        max_retries,
+        retry_budget: retry_budget.map(AtomicU64::new),
+        error_file,
+        pretty_errors,
+        warned: Mutex::new(HashSet::new()),
+        finished: Arc::new(AtomicBool::new(false)),
+        paused: Arc::new(AtomicBool::new(false)),
+        stats: Arc::new(Stats::default()),
+        observer,
+    });
+
+    let pool = threadpool::ThreadPool::new(min(slices, max_scroll_contexts.unwrap_or(slices)) as usize);
+    for slice_id in 0..slices {
+        pool.execute({
+            let task = task.clone();
+            let res_tx = res_tx.clone();
+            let err_tx = err_tx.clone();
+            move || {
+                // slices still waiting for a thread when the pull is stopped are not started at all
+                if task.finished.load(Ordering::Relaxed) {
+                    task.observer.finished(slice_id, false);
+                    res_tx.send(Page::Done(slice_id)).ok();
+                    return;
+                }
+                task.observer.starting(slice_id);
+                let res = pull_slice(&task, slice_id, &res_tx);
+                task.observer.finished(slice_id, res.is_ok());
+                if let Err(e) = res {
+                    task.stats.failed.fetch_add(1, Ordering::Relaxed);
+                    if task.fail_fast {
+                        task.finished.store(true, Ordering::Relaxed);
+                    }
+                    err_tx.send(e).expect("error sending to channel");
+                }
+                // the consumer may have quit already
+                res_tx.send(Page::Done(slice_id)).ok();
+            }
+        });
+    }
+
+    let pull = Pull {
+        pages:  res_rx,
+        errors: err_rx,
+        stop:   task.finished.clone(),
+        pause:  task.paused.clone(),
+        stats:  task.stats.clone(),
+    };
+    thread::spawn(move || {
+        pool.join();
+        if let Some(error_file) = &task.error_file {
+            if let Err(e) = error_file.flush() {
+                err_tx.send(format!("Error file error: {}", e)).ok();
+            }
+        }
+        drop(res_tx);
+    });
+    Ok(pull)
+}
+
+/// Pulls docs as json strings in the order they arrive, followed by the errors of the slices if any.
+///
+/// Dropping the iterator stops the pull.
+pub fn pull_stream(config: PullConfig) -> Result<impl Iterator<Item = Result<String>>> {
+    Ok(pull_raw(config)?.map(|doc| doc.map(|doc| doc.get().to_string())))
+}
+
+/// Like [`pull_stream`], but deserializes docs into `T`, e.g. `serde_json::Value`, [`Hit`] with `DocKind::Hit` or a
+/// struct of the source, which saves reparsing the json strings.
+pub fn pull_parsed<T: DeserializeOwned>(config: PullConfig) -> Result<impl Iterator<Item = Result<T>>> {
+    Ok(pull_raw(config)?.map(|doc| Ok(serde_json::from_str(doc?.get())?)))
+}
+
+fn pull_raw(config: PullConfig) -> Result<impl Iterator<Item = Result<Source>>> {
+    let Pull { pages, errors, .. } = start(config, Arc::new(()))?;
+    let docs = pages
+        .into_iter()
+        .flat_map(|page| match page {
+            Page::Docs(_, docs) => docs,
+            Page::Done(_) => vec![],
+        })
+        .map(Ok);
+    Ok(docs.chain(errors.into_iter().map(|e| Err(e.into()))))
+}
+
+/// Scrolls through one slice of the task and sends the fetched docs to `tx`.
+fn pull_slice(task: &Task, slice_id: u64, tx: &Sender<Page<Source>>) -> result::Result<(), String> {
+    let job_id = task.job_id(slice_id);
+    let client = &task.client;
+    let id = task.slice_ids[slice_id as usize];
+    let (index, query) = match task.indices.get(id as usize) {
+        Some(index) => (index, task.query.clone()),
+        None => (&task.index, inject_query(task.slice, id, task.query.clone())),
+    };
+
+    // never with a mapping type like `{index}/{type}/_search`, which 7 deprecates and 8 rejects
+    let url = endpoint(&task.host, &format!("{}/_search", index));
+    let mut batch = task.batch;
+    let mut backoff = Duration::from_secs(1);
+    let mut attempts = 1;
+    let mut retries = 0;
+    // a paused pull does not open more scrolls
+    wait_while_paused(task, None, slice_id)?;
+    let (res, permit, started) = loop {
+        let mut params = vec![("scroll", task.ttl.to_string()), ("size", batch.to_string())];
+        params.extend(task.params.iter().map(|(k, v)| (k.as_str(), v.clone())));
+        let params = Some(params);
+        let permit = task.permit();
+        let started = Instant::now();
+        match request_elastic(client, &url, &query, &task.user, &task.pass, &params) {
+            // the page size of a scroll is fixed by its first request, so this is the only chance to adapt it.
+            Err(e) if is_timeout(&*e) && batch > MIN_BATCH => {
+                drop(permit);
+                retry(task, slice_id, &mut retries, &*e)?;
+                let smaller = max(batch / 2, MIN_BATCH);
+                task.observer.log(
+                    slice_id,
+                    &format!(
+                        "Timeout[{}]: retry in {:?} with batch size {} (was {})",
+                        job_id, backoff, smaller, batch
+                    ),
+                );
+                thread::sleep(backoff);
+                backoff = min(backoff * 2, MAX_BACKOFF);
+                batch = smaller;
+            }
+            // contexts of other slices may be released in the meantime.
+            Err(e) if is_too_many_scrolls(&*e) => {
+                drop(permit);
+                if attempts >= MAX_ATTEMPTS {
+                    return Err(format!(
+                        "Fetch error[{}]: the cluster refused to open more scroll contexts, \
+                         lower --max-scroll-contexts or raise search.max_open_scroll_context of the cluster",
+                        job_id
+                    ));
+                }
+                retry(task, slice_id, &mut retries, &*e)?;
+                task.observer.log(
+                    slice_id,
+                    &format!("Too many scroll contexts[{}]: retry in {:?}", job_id, backoff),
+                );
+                thread::sleep(backoff);
+                backoff = min(backoff * 2, MAX_BACKOFF);
+                attempts += 1;
+            }
+            res => {
+                let res =
+                    res.map_err(|e| format!("Fetch error[{}]: {}", job_id, describe_error(&*e, task.pretty_errors)))?;
+                break (res, permit, started);
+            }
+        }
+    };
+    task.log_warnings(slice_id, &res);
+    let mut page =
+        parse_response(res, task.kind, task.extras).map_err(|e| format!("Parse error[{}]: {}", job_id, e))?;
+    drop(permit);
+    task.stats.record_page(&page, started);
+    check_shards(task, &page.shards, slice_id)?;
+
+    task.stats.reported.fetch_add(page.total, Ordering::Relaxed);
+    task.stats.started.fetch_add(1, Ordering::Relaxed);
+    task.observer.started(slice_id, page.total);
+
+    let mut cursor = Cursor {
+        client,
+        scroll_id: String::new(),
+        total: page.total,
+        fetched: 0,
+        renewed: Instant::now(),
+        ahead: VecDeque::new(),
+        exhausted: false,
+        retries,
+        retried: false,
+    };
+    let res = advance(task, &mut cursor, &mut page, slice_id)
+        .and_then(|_| scroll_slice(task, &mut cursor, slice_id, page, tx));
+    let scroll_id = cursor.scroll_id;
+    if scroll_id.is_empty() || !task.clear_scroll {
+        return res;
+    }
+    // release the scroll context early instead of waiting for ttl, so other slices can open theirs.
+    let permit = task.permit();
+    let cleared = clear_scroll(client, &task.host, &scroll_id, &task.user, &task.pass);
+    drop(permit);
+    if let Err(e) = cleared {
+        task.observer.log(
+            slice_id,
+            &format!("Clear error[{}]: {}", job_id, describe_error(&*e, task.pretty_errors)),
+        );
+    }
+    res
+}
+
+/// Counts a retry of the slice, failing it if it has used up its retries, or stopping the pull if all slices have
+/// used up the retry budget.
+fn retry(task: &Task, slice_id: u64, retries: &mut u32, e: &(dyn Error + 'static)) -> result::Result<(), String> {
+    let exhausted = task.retry_budget.as_ref().is_some_and(|budget| {
+        budget
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| left.checked_sub(1))
+            .is_err()
+    });
+    if exhausted {
+        task.finished.store(true, Ordering::Relaxed);
+        return Err(format!(
+            "Fetch error[{}]: the retry budget of the pull is used up, stopping all slices: {}",
+            task.job_id(slice_id),
+            describe_error(e, task.pretty_errors)
+        ));
+    }
+    *retries += 1;
+    if let Some(max) = task.max_retries {
+        if *retries > max {
+            return Err(format!(
+                "Fetch error[{}]: gave up after {} retries: {}",
+                task.job_id(slice_id),
+                max,
+                describe_error(e, task.pretty_errors)
+            ));
+        }
+    }
+    task.stats.retries.fetch_add(1, Ordering::Relaxed);
+    task.observer.retrying(slice_id, *retries);
+    Ok(())
+}
+
+/// Where a slice is in its scroll.
+struct Cursor<'a> {
+    client:    &'a Client,
+    scroll_id: String,
+    /// Total hits of the latest page.
+    total:     u64,
+    /// Hits fetched so far, including those of the pages ahead.
+    fetched:   u64,
+    /// When the scroll was last requested.
+    renewed:   Instant,
+    /// Pages fetched ahead of the output.
+    ahead:     VecDeque<SearchPage>,
+    /// Whether the last page has been fetched.
+    exhausted: bool,
+    /// Retries of the slice so far, counted against --max-retries-per-slice.
+    retries:   u32,
+    /// Whether a page has been requested again after a timeout, which may have lost the page.
+    retried:   bool,
+}
+
+/// Sends the first page of a scroll and fetches the remaining ones, until the last one or until the consumer quits.
+fn scroll_slice(
+    task: &Task,
+    cursor: &mut Cursor,
+    slice_id: u64,
+    first: SearchPage,
+    tx: &Sender<Page<Source>>,
+) -> result::Result<(), String> {
+    let mut page = first;
+    while !page.docs.is_empty() && !task.finished.load(Ordering::Relaxed) {
+        let docs = skip_docs(task, page.docs, page.lossy, slice_id)?;
+        if !send_docs(task, tx, cursor, slice_id, docs, page.total)? {
+            break;
+        }
+        wait_while_paused(task, Some(&mut *cursor), slice_id)?;
+        page = match cursor.ahead.pop_front() {
+            Some(page) => page,
+            // the scroll ended without an empty page
+            None if cursor.exhausted => break,
+            None => fetch_page(task, cursor, slice_id)?,
+        };
+    }
+    Ok(())
+}
+
+/// Waits while the pull is paused, keeping the scroll of the cursor alive.
+fn wait_while_paused(task: &Task, mut cursor: Option<&mut Cursor>, slice_id: u64) -> result::Result<(), String> {
+    if !task.paused.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+    task.observer.paused(slice_id, true);
+    while task.paused.load(Ordering::Relaxed) && !task.finished.load(Ordering::Relaxed) {
+        if let Some(cursor) = cursor.as_deref_mut() {
+            if !cursor.exhausted && cursor.renewed.elapsed() >= task.ttl.duration / 2 {
+                let ahead = fetch_page(task, cursor, slice_id)?;
+                cursor.ahead.push_back(ahead);
+                task.observer.log(
+                    slice_id,
+                    &format!(
+                        "Keep-alive[{}]: paused, fetched a page ahead to keep the scroll alive ({} pages ahead)",
+                        task.job_id(slice_id),
+                        cursor.ahead.len()
+                    ),
+                );
+            }
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    task.observer.paused(slice_id, false);
+    Ok(())
+}
+
+/// Fetches the next page of the scroll, keeping the cursor updated to the latest one.
+fn fetch_page(task: &Task, cursor: &mut Cursor, slice_id: u64) -> result::Result<SearchPage, String> {
+    let job_id = task.job_id(slice_id);
+    let url = endpoint(&task.host, "_search/scroll");
+    let query = json!({ "scroll": task.ttl.to_string(), "scroll_id": cursor.scroll_id, });
+    let mut backoff = Duration::from_secs(1);
+    let mut attempts = 1;
+    let (res, permit, started) = loop {
+        let permit = task.permit();
+        let started = Instant::now();
+        match request_elastic(cursor.client, &url, &query, &task.user, &task.pass, &None) {
+            // the scroll id stays valid within the ttl, so the page is requested again with it
+            Err(e) if is_transient(&*e) && attempts < MAX_ATTEMPTS => {
+                drop(permit);
+                retry(task, slice_id, &mut cursor.retries, &*e)?;
+                cursor.retried |= is_timeout(&*e);
+                task.observer.log(
+                    slice_id,
+                    &format!(
+                        "Retry[{}]: retry the page in {:?} after {}",
+                        job_id,
+                        backoff,
+                        describe_error(&*e, task.pretty_errors)
+                    ),
+                );
+                thread::sleep(backoff);
+                backoff = min(backoff * 2, MAX_BACKOFF);
+                attempts += 1;
+            }
+            res => {
+                let res = res.map_err(|e| format!("Error[{}]: {}", job_id, describe_error(&*e, task.pretty_errors)))?;
+                break (res, permit, started);
+            }
+        }
+    };
+    cursor.renewed = Instant::now();
+    task.log_warnings(slice_id, &res);
+    let mut page = parse_response(res, task.kind, task.extras).map_err(|e| format!("Error[{}]: {}", job_id, e))?;
+    drop(permit);
+    task.stats.record_page(&page, started);
+    check_shards(task, &page.shards, slice_id)?;
+    advance(task, cursor, &mut page, slice_id)?;
+    if page.total != cursor.total {
+        task.stats.reported.fetch_add(page.total, Ordering::Relaxed);
+        task.stats.reported.fetch_sub(cursor.total, Ordering::Relaxed);
+        cursor.total = page.total;
+    }
+    Ok(page)
+}
+
+/// Moves the cursor past the fetched page, taking over its scroll id.
+///
+/// A response without a scroll id ends the scroll, which is an error unless all hits have been fetched, as there is no
+/// way to request the rest.
+fn advance(task: &Task, cursor: &mut Cursor, page: &mut SearchPage, slice_id: u64) -> result::Result<(), String> {
+    cursor.fetched += page.docs.len() as u64;
+    cursor.exhausted = page.docs.is_empty();
+    // the cluster may have answered the page which timed out, moving the scroll past it
+    if cursor.exhausted && cursor.retried && cursor.fetched < page.total {
+        return Err(format!(
+            "Scroll error[{}]: a page retried after a timeout was lost, only {} of {} hits are fetched",
+            task.job_id(slice_id),
+            cursor.fetched,
+            page.total
+        ));
+    }
+    if !page.scroll_id.is_empty() {
+        cursor.scroll_id = mem::take(&mut page.scroll_id);
+        return Ok(());
+    }
+    if !cursor.exhausted && cursor.fetched < page.total {
+        return Err(format!(
+            "Scroll error[{}]: the response has no _scroll_id, but only {} of {} hits are fetched",
+            task.job_id(slice_id),
+            cursor.fetched,
+            page.total
+        ));
+    }
+    cursor.exhausted = true;
+    Ok(())
+}
+
+/// Drops docs larger than `max_doc_bytes` or with invalid UTF-8 in a `lossy` page, recording them in the error file
+/// if there is one.
+fn skip_docs(task: &Task, docs: Vec<Source>, lossy: bool, slice_id: u64) -> result::Result<Vec<Source>, String> {
+    let job_id = task.job_id(slice_id);
+    if task.max_doc_bytes.is_none() && !lossy {
+        return Ok(docs);
+    }
+    if lossy {
+        task.observer.log(
+            slice_id,
+            &format!("Warning[{}]: the response contains invalid UTF-8", job_id),
+        );
+    }
+    let mut kept = Vec::with_capacity(docs.len());
+    for doc in docs {
+        let len = doc.get().len();
+        let reason = match task.max_doc_bytes {
+            Some(max) if len > max => format!("document of {} bytes exceeds --max-doc-bytes {}", len, max),
+            _ if lossy && doc.get().contains('\u{FFFD}') => {
+                "document contains invalid UTF-8, which is replaced with U+FFFD".to_string()
+            }
+            _ => {
+                kept.push(doc);
+                continue;
+            }
+        };
+        task.stats.skipped.fetch_add(1, Ordering::Relaxed);
+        match &task.error_file {
+            Some(error_file) => error_file
+                .record(&reason, &doc)
+                .map_err(|e| format!("Error file error[{}]: {}", job_id, e))?,
+            None => task
+                .observer
+                .log(slice_id, &format!("Warning[{}]: skipped: {}", job_id, reason)),
+        }
+    }
+    Ok(kept)
+}
+
+/// Reports failed shards, whose docs are missing from the page, as a warning or as an error if the task should fail.
+fn check_shards(task: &Task, shards: &Shards, slice_id: u64) -> result::Result<(), String> {
+    let job_id = task.job_id(slice_id);
+    if shards.failed == 0 {
+        return Ok(());
+    }
+    let msg = format!("{} of {} shards failed", shards.failed, shards.total);
+    let reasons: String = shards.failures.iter().map(|f| format!("\n    {}", f)).collect();
+    if task.fail_on_shard_failure {
+        task.finished.store(true, Ordering::Relaxed);
+        return Err(format!("Shard error[{}]: {}{}", job_id, msg, reasons));
+    }
+    task.observer.log(
+        slice_id,
+        &format!("Warning[{}]: {}, the result may be incomplete{}", job_id, msg, reasons),
+    );
+    Ok(())
+}
+
+/// Sends docs to the consumer and returns whether it is still receiving, fetching pages ahead while it is slow.
+fn send_docs(
+    task: &Task,
+    tx: &Sender<Page<Source>>,
+    cursor: &mut Cursor,
+    slice_id: u64,
+    docs: Vec<Source>,
+    total: u64,
+) -> result::Result<bool, String> {
+    let len = docs.len() as u64;
+    let mut page = Page::Docs(slice_id, docs);
+    loop {
+        if cursor.exhausted {
+            if tx.send(page).is_err() {
+                return Ok(false);
+            }
+            break;
+        }
+        let wait = (task.ttl.duration / 2).saturating_sub(cursor.renewed.elapsed());
+        match tx.send_timeout(page, wait) {
+            Ok(()) => break,
+            Err(SendTimeoutError::Disconnected(_)) => return Ok(false),
+            Err(SendTimeoutError::Timeout(unsent)) => {
+                page = unsent;
+                let ahead = fetch_page(task, cursor, slice_id)?;
+                cursor.ahead.push_back(ahead);
+                task.observer.log(
+                    slice_id,
+                    &format!(
+                        "Keep-alive[{}]: the output is slow, fetched a page ahead to keep the scroll alive ({} pages \
+                         ahead)",
+                        task.job_id(slice_id),
+                        cursor.ahead.len()
+                    ),
+                );
+            }
+        }
+    }
+    task.observer.fetched(slice_id, len, total);
+    Ok(true)
+}
+
+/// Restricts the query to one slice, it is left as it is with a single slice.
+fn inject_query(slice: u64, slice_id: u64, mut query: Value) -> Value {
+    if let (true, Some(obj)) = (slice > 1, query.as_object_mut()) {
+        obj.insert("slice".into(), json!({ "id": slice_id, "max": slice }));
+    }
+    query
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read};
+    use std::net::TcpListener;
+
+    /// Serves each request with the status and the body `respond` returns for its method and path, and records them.
+    fn serve(respond: fn(&str, &str) -> (u16, Value)) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let host = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(vec![]));
+        let log = requests.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let mut parts = line.split_whitespace();
+                let (method, path) = (parts.next().unwrap().to_string(), parts.next().unwrap().to_string());
+                let mut len = 0;
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header.trim().is_empty() {
+                        break;
+                    }
+                    if let Some(value) = header.to_ascii_lowercase().strip_prefix("content-length:") {
+                        len = value.trim().parse().unwrap();
+                    }
+                }
+                reader.read_exact(&mut vec![0; len]).unwrap();
+                let (status, body) = respond(&method, &path);
+                let body = body.to_string();
+                write!(
+                    stream,
+                    "HTTP/1.1 {} X\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+                     Connection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                )
+                .unwrap();
+                log.lock().unwrap().push(format!("{} {}", method, path));
+            }
+        });
+        (host, requests)
+    }
+
+    #[test]
+    fn pull_of_no_hits_ends_without_docs_and_errors() {
+        let (host, requests) = serve(|method, _| match method {
+            "DELETE" => (200, cleared()),
+            _ => (200, page(0, &[])),
+        });
+        let Pull {
+            pages, errors, stats, ..
+        } = start(PullConfig::new(host, "empty"), Arc::new(())).unwrap();
+        let pages: Vec<_> = pages.iter().collect();
+        let errors: Vec<_> = errors.iter().collect();
+
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert!(matches!(pages[..], [Page::Done(0)]));
+        assert_eq!(stats.reported.load(Ordering::Relaxed), 0);
+        let requests = requests.lock().unwrap();
+        assert!(requests[0].starts_with("POST /empty/_search?"), "{:?}", requests);
+        assert!(
+            !requests.iter().any(|request| request.contains("/_search/scroll?")),
+            "{:?}",
+            requests
+        );
+    }
+
+    /// Response of a search or scroll request with hits of the ids out of `total`.
+    fn page(total: u64, ids: &[u64]) -> Value {
+        let hits: Vec<_> = ids
+            .iter()
+            .map(|id| json!({ "_index": "logs", "_id": id.to_string(), "_source": { "n": id } }))
+            .collect();
+        json!({
+            "_scroll_id": "s1",
+            "took": 1,
+            "timed_out": false,
+            "_shards": { "total": 1, "successful": 1, "skipped": 0, "failed": 0 },
+            "hits": { "total": { "value": total, "relation": "eq" }, "hits": hits }
+        })
+    }
+
+    fn cleared() -> Value {
+        json!({ "succeeded": true, "num_freed": 1 })
+    }
+
+    #[test]
+    fn ttl_parses_each_unit() {
+        let duration = |s: &str| s.parse::<Ttl>().unwrap().duration;
+        assert_eq!(duration("2d"), Duration::from_secs(2 * 24 * 3600));
+        assert_eq!(duration("3h"), Duration::from_secs(3 * 3600));
+        assert_eq!(duration("5m"), Duration::from_secs(300));
+        assert_eq!(duration(" 30s "), Duration::from_secs(30));
+        assert_eq!(duration("250ms"), Duration::from_millis(250));
+        assert_eq!(duration("7micros"), Duration::from_micros(7));
+        assert_eq!(duration("9nanos"), Duration::from_nanos(9));
+        // it is sent to the cluster as given
+        assert_eq!("90s".parse::<Ttl>().unwrap().to_string(), "90s");
+    }
+
+    #[test]
+    fn ttl_rejects_zero_and_missing_units() {
+        let error = |s: &str| s.parse::<Ttl>().unwrap_err();
+        assert!(error("0s").contains("positive duration"), "{}", error("0s"));
+        assert!(error("m").contains("positive duration"), "{}", error("m"));
+        assert_eq!(error("30"), "missing time unit in `30`, e.g. `30m`");
+        assert!(error("3w").starts_with("unknown time unit `w`"), "{}", error("3w"));
+    }
+
+    #[test]
+    fn failed_page_is_retried_with_the_same_scroll() {
+        static SCROLLS: AtomicU64 = AtomicU64::new(0);
+        let (host, requests) = serve(|method, path| match (method, path) {
+            ("DELETE", _) => (200, cleared()),
+            (_, path) if path.starts_with("/_search/scroll") => match SCROLLS.fetch_add(1, Ordering::Relaxed) {
+                0 => (503, json!({ "error": "unavailable" })),
+                1 => (200, page(3, &[2])),
+                _ => (200, page(3, &[])),
+            },
+            _ => (200, page(3, &[0, 1])),
+        });
+        let mut config = PullConfig::new(host, "logs");
+        config.retry_budget = Some(1);
+        let Pull {
+            pages, errors, stats, ..
+        } = start(config, Arc::new(())).unwrap();
+        let docs: usize = pages
+            .iter()
+            .map(|page| match page {
+                Page::Docs(_, docs) => docs.len(),
+                _ => 0,
+            })
+            .sum();
+        let errors: Vec<_> = errors.iter().collect();
+
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(docs, 3);
+        assert_eq!(stats.retries.load(Ordering::Relaxed), 1);
+        let requests = requests.lock().unwrap();
+        let scrolls = requests
+            .iter()
+            .filter(|request| request.starts_with("POST /_search/scroll"))
+            .count();
+        assert_eq!(scrolls, 3, "{:?}", requests);
+    }
+
+    #[test]
+    fn slice_gives_up_after_max_retries() {
+        let (host, _) = serve(|method, path| match (method, path) {
+            ("DELETE", _) => (200, cleared()),
+            (_, path) if path.starts_with("/_search/scroll") => (503, json!({ "error": "unavailable" })),
+            _ => (200, page(3, &[0, 1])),
+        });
+        let mut config = PullConfig::new(host, "logs");
+        config.max_retries = Some(2);
+        let Pull {
+            pages, errors, stats, ..
+        } = start(config, Arc::new(())).unwrap();
+        pages.iter().for_each(drop);
+        let errors: Vec<_> = errors.iter().collect();
+
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        assert!(errors[0].contains("gave up after 2 retries"), "{}", errors[0]);
+        assert_eq!(stats.retries.load(Ordering::Relaxed), 2);
+    }
+}
@@ -0,0 +1,34 @@
+use estunnel::common::Result;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Set by SIGUSR1 and cleared by SIGUSR2.
+static PAUSED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_signal(signal: libc::c_int) {
+    // only an atomic store, which is safe in a signal handler
+    PAUSED.store(signal == libc::SIGUSR1, Ordering::Relaxed);
+}
+
+/// Installs the handlers of SIGUSR1, which pauses the pull, and SIGUSR2, which resumes it.
+pub fn handle_pause() -> Result<()> {
+    for signal in [libc::SIGUSR1, libc::SIGUSR2].iter() {
+        // SA_RESTART keeps the signals from interrupting the reads and writes of the pull
+        let res = unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESTART;
+            libc::sigemptyset(&mut action.sa_mask);
+            libc::sigaction(*signal, &action, std::ptr::null_mut())
+        };
+        if res != 0 {
+            return Err(format!("cannot handle signal {}: {}", signal, io::Error::last_os_error()).into());
+        }
+    }
+    Ok(())
+}
+
+/// Whether the pull is paused by SIGUSR1.
+pub fn paused() -> bool {
+    PAUSED.load(Ordering::Relaxed)
+}
@@ -0,0 +1,67 @@
+use estunnel::common::Result;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::thread;
+
+/// Tunnels the connections of a local proxy port to `addr`, whatever host the client asks the proxy for.
+///
+/// Returns the url of the proxy. Going through it, the client asks for the host named in the certificate, which TLS
+/// sends as server name and validates the certificate against, while the bytes go to `addr`, like the ip address of
+/// the cluster. It only listens on the loopback interface until the process exits.
+pub fn tunnel(addr: String) -> Result<String> {
+    // fail early if the cluster is not there
+    TcpStream::connect(&addr).map_err(|e| format!("error connecting to {}: {}", addr, e))?;
+
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let url = format!("http://{}", listener.local_addr()?);
+    thread::spawn(move || {
+        for client in listener.incoming().flatten() {
+            let addr = addr.clone();
+            thread::spawn(move || connect(client, &addr).ok());
+        }
+    });
+    Ok(url)
+}
+
+/// Answers the CONNECT request of the client and pipes the connection to `addr`.
+fn connect(mut client: TcpStream, addr: &str) -> io::Result<()> {
+    let head = read_head(&mut client)?;
+    if !head.starts_with(b"CONNECT ") {
+        return client.write_all(b"HTTP/1.1 405 Method Not Allowed\r\nContent-Length: 0\r\n\r\n");
+    }
+    match TcpStream::connect(addr) {
+        Ok(server) => {
+            client.write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n")?;
+            pipe(client, server)
+        }
+        // lets the client fail the request
+        Err(_) => client.write_all(b"HTTP/1.1 502 Bad Gateway\r\nContent-Length: 0\r\n\r\n"),
+    }
+}
+
+/// Reads the request head byte by byte, so nothing sent after it is consumed.
+fn read_head(client: &mut TcpStream) -> io::Result<Vec<u8>> {
+    let mut head = vec![];
+    let mut byte = [0];
+    while !head.ends_with(b"\r\n\r\n") {
+        if client.read(&mut byte)? == 0 || head.len() > 8192 {
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+        head.push(byte[0]);
+    }
+    Ok(head)
+}
+
+/// Copies bytes in both directions until both peers are done.
+fn pipe(client: TcpStream, server: TcpStream) -> io::Result<()> {
+    let (mut client_rx, mut client_tx) = (client.try_clone()?, client);
+    let (mut server_rx, mut server_tx) = (server.try_clone()?, server);
+    let upstream = thread::spawn(move || {
+        io::copy(&mut client_rx, &mut server_tx).ok();
+        server_tx.shutdown(Shutdown::Write).ok();
+    });
+    io::copy(&mut server_rx, &mut client_tx)?;
+    client_tx.shutdown(Shutdown::Write)?;
+    upstream.join().ok();
+    Ok(())
+}
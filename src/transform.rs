@@ -0,0 +1,96 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde_json::Value;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    Base64Decode,
+    HexDecode,
+}
+
+impl Kind {
+    fn name(self) -> &'static str {
+        match self {
+            Kind::Base64Decode => "base64-decode",
+            Kind::HexDecode => "hex-decode",
+        }
+    }
+
+    /// Decodes the text into the UTF-8 string it encodes.
+    fn apply(self, s: &str) -> Option<String> {
+        let bytes = match self {
+            Kind::Base64Decode => STANDARD.decode(s.trim()).ok()?,
+            Kind::HexDecode => hex_decode(s.trim())?,
+        };
+        String::from_utf8(bytes).ok()
+    }
+}
+
+fn hex_decode(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// A built-in transform of named fields of the docs, like `base64-decode:user.ssn,card`.
+#[derive(Debug, Clone)]
+pub struct Transform {
+    kind:   Kind,
+    fields: Vec<Vec<String>>,
+}
+
+impl FromStr for Transform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, fields) = s
+            .split_once(':')
+            .ok_or_else(|| format!("expect `<transform>:<field>,...`, got `{}`", s))?;
+        let kind = match name {
+            "base64-decode" => Kind::Base64Decode,
+            "hex-decode" => Kind::HexDecode,
+            name => return Err(format!("expect one of base64-decode and hex-decode, got `{}`", name)),
+        };
+        let fields: Vec<_> = fields
+            .split(',')
+            .filter(|field| !field.is_empty())
+            .map(|field| field.split('.').map(String::from).collect())
+            .collect();
+        if fields.is_empty() {
+            return Err(format!("no field to {} in `{}`", name, s));
+        }
+        Ok(Transform { kind, fields })
+    }
+}
+
+impl Transform {
+    /// Transforms the fields of the doc, or each string of a field holding an array, fields missing from it are left
+    /// out.
+    pub fn apply(&self, doc: &mut Value) -> Result<(), String> {
+        for path in &self.fields {
+            let value = path.iter().try_fold(&mut *doc, |value, key| value.get_mut(key));
+            let values = match value {
+                Some(Value::Array(values)) => values.iter_mut().collect(),
+                Some(value) => vec![value],
+                None => continue,
+            };
+            for value in values {
+                let transformed = match &*value {
+                    Value::Null => continue,
+                    Value::String(s) => self.kind.apply(s),
+                    _ => None,
+                };
+                *value =
+                    Value::String(transformed.ok_or_else(|| {
+                        format!("cannot {} `{}` of field `{}`", self.kind.name(), value, path.join("."))
+                    })?);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,123 @@
+use estunnel::common::Result;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::thread;
+
+/// Header of the token the forwarding port takes connections with.
+pub const TOKEN_HEADER: &str = "X-Estunnel-Forward";
+
+/// Most bytes of the head of the first request of a connection.
+const MAX_HEAD: u64 = 64 * 1024;
+
+/// Forwards connections of a local tcp port to the unix domain socket at `path`, as the http client can only talk tcp.
+///
+/// Returns the url of the forwarding port and the token to send in the `TOKEN_HEADER` of each request. The port only
+/// listens on the loopback interface until the process exits, and drops connections of other local users, whose
+/// first request lacks the token. Each connection carries a single request, as only the head of the first one is read.
+pub fn forward(path: PathBuf) -> Result<(String, String)> {
+    // fail early if the socket is not there
+    UnixStream::connect(&path).map_err(|e| format!("error connecting to {}: {}", path.display(), e))?;
+
+    let mut bytes = [0; 16];
+    openssl::rand::rand_bytes(&mut bytes)?;
+    let token: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let url = format!("http://{}", listener.local_addr()?);
+    thread::spawn({
+        let token = token.clone();
+        move || {
+            for tcp in listener.incoming().flatten() {
+                let path = path.clone();
+                let token = token.clone();
+                thread::spawn(move || pipe(tcp, &path, &token).ok());
+            }
+        }
+    });
+    Ok((url, token))
+}
+
+/// Copies bytes in both directions until both peers are done, once the first request has the token.
+fn pipe(tcp: TcpStream, path: &Path, token: &str) -> io::Result<()> {
+    let mut tcp_rx = BufReader::new(tcp.try_clone()?);
+    let head = match authorized_head(&mut tcp_rx, token)? {
+        Some(head) => head,
+        // dropping the tcp stream lets the client fail the request
+        None => return Ok(()),
+    };
+    let unix = UnixStream::connect(path)?;
+    let mut tcp_tx = tcp;
+    let (mut unix_rx, mut unix_tx) = (unix.try_clone()?, unix);
+    let upstream = thread::spawn(move || {
+        // the rest of the buffer goes first
+        unix_tx
+            .write_all(&head)
+            .and_then(|_| io::copy(&mut tcp_rx, &mut unix_tx))
+            .ok();
+        unix_tx.shutdown(Shutdown::Write).ok();
+    });
+    io::copy(&mut unix_rx, &mut tcp_tx)?;
+    tcp_tx.shutdown(Shutdown::Write)?;
+    upstream.join().ok();
+    Ok(())
+}
+
+/// Reads the head of the first request, which is returned without the token header if it has the token.
+///
+/// The head asks the server to close the connection after the response, so later requests of the client, whose token
+/// would reach the server, go through new connections.
+fn authorized_head(tcp: &mut BufReader<TcpStream>, token: &str) -> io::Result<Option<Vec<u8>>> {
+    let mut head = vec![];
+    let mut authorized = false;
+    let mut reader = tcp.take(MAX_HEAD);
+    loop {
+        let mut line = vec![];
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            return Ok(None);
+        }
+        let text = String::from_utf8_lossy(&line);
+        match text.split_once(':') {
+            Some((name, value)) if name.trim().eq_ignore_ascii_case(TOKEN_HEADER) => {
+                authorized = value.trim() == token;
+                continue;
+            }
+            Some((name, _)) if name.trim().eq_ignore_ascii_case("Connection") => continue,
+            _ => {}
+        }
+        if text.trim().is_empty() {
+            head.extend_from_slice(b"Connection: close\r\n");
+            head.extend_from_slice(&line);
+            break;
+        }
+        head.extend_from_slice(&line);
+    }
+    Ok(match authorized {
+        true => Some(head),
+        false => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn head_has_no_token_and_closes_the_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        write!(
+            client,
+            "GET / HTTP/1.1\r\nHost: es\r\n{}: t1\r\nConnection: keep-alive\r\n\r\nGET /next HTTP/1.1\r\n",
+            TOKEN_HEADER
+        )
+        .unwrap();
+
+        let head = authorized_head(&mut BufReader::new(server), "t1").unwrap().unwrap();
+        assert_eq!(
+            String::from_utf8(head).unwrap(),
+            "GET / HTTP/1.1\r\nHost: es\r\nConnection: close\r\n\r\n"
+        );
+    }
+}
@@ -0,0 +1,148 @@
+use crate::date;
+use estunnel::common::Result;
+use serde_json::{json, Value};
+use std::cmp::Ordering;
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// The max value of a field in the docs of an export, saved so the next export only pulls docs above it.
+///
+/// It is saved as a json object like `{"field": "@timestamp", "value": "2023-01-02T03:04:05Z"}`.
+pub struct Watermark {
+    field: String,
+    path:  PathBuf,
+    /// Whether docs are whole hits, whose field is in `_source`.
+    hits:  bool,
+    /// Value saved by the last export, none on the first one.
+    last:  Option<Value>,
+    max:   Mutex<Option<Value>>,
+}
+
+impl Watermark {
+    pub fn load(path: &Path, field: &str, hits: bool) -> Result<Watermark> {
+        let last = match File::open(path) {
+            Ok(file) => {
+                let saved: Value = serde_json::from_reader(BufReader::new(file))?;
+                match saved["field"].as_str() {
+                    Some(saved) if saved == field => {}
+                    saved => {
+                        return Err(
+                            format!("the watermark is of field {}, not {}", saved.unwrap_or("(none)"), field).into(),
+                        )
+                    }
+                }
+                Some(saved["value"].clone()).filter(|value| !value.is_null())
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Watermark {
+            field: field.to_string(),
+            path: path.to_path_buf(),
+            hits,
+            last,
+            max: Mutex::new(None),
+        })
+    }
+
+    pub fn last(&self) -> Option<&Value> {
+        self.last.as_ref()
+    }
+
+    /// Keeps the value of the field in the doc if it is the max so far.
+    pub fn observe(&self, doc: &Value) {
+        let doc = match self.hits {
+            true => &doc["_source"],
+            false => doc,
+        };
+        // the fields API and doc values return arrays under the full field name
+        let value = match doc.get(&self.field) {
+            Some(value) => value,
+            None => match self.field.split('.').try_fold(doc, |value, key| value.get(key)) {
+                Some(value) => value,
+                None => return,
+            },
+        };
+        let values = match value {
+            Value::Array(values) => values.iter().collect(),
+            value => vec![value],
+        };
+        let mut max = self.max.lock().unwrap();
+        for value in values {
+            if max
+                .as_ref()
+                .map_or(!value.is_null(), |max| compare(value, max) == Some(Ordering::Greater))
+            {
+                *max = Some(value.clone());
+            }
+        }
+    }
+
+    /// Saves the max value seen if it is above the last one, returns the saved value if any.
+    pub fn save(&self) -> Result<Option<Value>> {
+        let max = match self.max.lock().unwrap().clone() {
+            Some(max) => max,
+            None => return Ok(None),
+        };
+        if let Some(last) = &self.last {
+            if compare(&max, last) != Some(Ordering::Greater) {
+                return Ok(None);
+            }
+        }
+        // replace the file at once, so an interrupted save cannot lose the last watermark
+        let tmp = PathBuf::from(format!("{}.tmp", self.path.display()));
+        fs::write(
+            &tmp,
+            serde_json::to_string_pretty(&json!({ "field": self.field, "value": max }))?,
+        )?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(Some(max))
+    }
+}
+
+/// Compares numbers by value and strings as dates, or lexically if neither is a date. Other values, and a date with a
+/// string which is not one, are not comparable.
+fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64()?.partial_cmp(&b.as_f64()?),
+        // lexical order is wrong for dates with offsets or fractions of different lengths
+        (Value::String(sa), Value::String(sb)) => match (date::millis(a), date::millis(b)) {
+            (Some(a), Some(b)) => Some(a.cmp(&b)),
+            (None, None) => Some(sa.cmp(sb)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strings_compare_as_dates() {
+        let cmp = |a: &str, b: &str| compare(&json!(a), &json!(b));
+        // lexically the fraction sorts first
+        assert_eq!(
+            cmp("2023-01-31T08:00:00Z", "2023-01-31T08:00:00.5Z"),
+            Some(Ordering::Less)
+        );
+        assert_eq!(
+            cmp("2023-01-31T08:00:00.9Z", "2023-01-31T08:00:01Z"),
+            Some(Ordering::Less)
+        );
+        // the offset makes the first one 8 hours earlier
+        assert_eq!(
+            cmp("2023-01-31T10:00:00+08:00", "2023-01-31T03:00:00Z"),
+            Some(Ordering::Less)
+        );
+        assert_eq!(
+            cmp("2023-01-31T16:00:00+08:00", "2023-01-31T08:00:00.000Z"),
+            Some(Ordering::Equal)
+        );
+        assert_eq!(cmp("b", "a"), Some(Ordering::Greater));
+        assert_eq!(cmp("2023-01-31", "a"), None);
+    }
+}